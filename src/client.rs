@@ -1,16 +1,43 @@
 use anyhow::{anyhow, Result};
-use std::{net::{IpAddr, Ipv4Addr, SocketAddr}, sync::Arc};
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, LazyLock},
+};
 
-use tokio::{net::TcpStream, sync::Mutex};
+use tokio::{
+    net::TcpStream,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        Mutex,
+    },
+};
 
 use crate::{
     daemon::{
         node::Node,
         protocol::{Message, Method, Payload, Response},
     },
-    notification::Notification, AppMode,
+    events, metrics,
+    notification::{icon, Notification, NotificationId},
+    peers::{self, PeerInfo},
+    AppMode,
 };
 
+/// a notification made on this node has nowhere to go
+#[derive(Debug)]
+pub struct NoHost;
+
+impl Display for NoHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no host found")
+    }
+}
+
+impl std::error::Error for NoHost {}
+
 pub struct Client {
     node: Arc<Node<Response>>,
     host: Arc<Mutex<AppMode<SocketAddr>>>,
@@ -22,7 +49,7 @@ impl Client {
     }
 
     pub async fn connect(&self, socket: SocketAddr) -> Result<StreamClient> {
-        let stream = TcpStream::connect(socket).await?;
+        let stream = TcpStream::connect(socket).await.inspect_err(|_| metrics::CONNECTION_ERRORS.inc())?;
         Ok(StreamClient {
             node: self.node.clone(),
             host: self.host.clone(),
@@ -30,6 +57,27 @@ impl Client {
         })
     }
 
+    /// display a notification made on this node on the host, from a client it is forwarded like a captured one
+    pub async fn deliver(&self, mut notification: Notification) -> Result<NotificationId> {
+        notification.id = NotificationId::next();
+        let id = notification.id.clone();
+
+        let mode = self.host.lock().await.clone();
+        match mode {
+            AppMode::Host => crate::notification::sink::dispatch(notification).await,
+            AppMode::Client(Some(host)) => {
+                info!("send notification to {}", host);
+                self.connect(host).await?.send_notification(notification).await?;
+            },
+            AppMode::Client(None) => {
+                metrics::DROPPED_NO_HOST.inc();
+                return Err(NoHost.into());
+            },
+        }
+
+        Ok(id)
+    }
+
     pub fn handle(&self) -> MessageHandler {
         MessageHandler {
             node: self.node.clone(),
@@ -51,12 +99,14 @@ impl StreamClient {
             .send_and_wait_response(&mut self.stream, msg)
             .await?;
 
-        if res.is_host_changed() {
-            if let Some(Payload::Address(a, b, c, d, p)) = res.result {
-                println!("host changed to {}.{}.{}.{}:{}", a, b, c, d, p);
-                *self.host.lock().await = AppMode::Client(Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), p)));
-                return Err(anyhow!("host changed"))
-            }
+        if res.is_host_changed()
+            && let Some(Payload::Address(a, b, c, d, p)) = res.result
+        {
+            info!("host changed to {}.{}.{}.{}:{}", a, b, c, d, p);
+            let mut host = self.host.lock().await;
+            *host = AppMode::Client(Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), p)));
+            events::host_changed(&host);
+            return Err(anyhow!("host changed"))
         }
 
         Ok(res)
@@ -82,15 +132,40 @@ impl StreamClient {
         }
     }
 
-    pub async fn send_notification(&mut self, notif: Notification) -> Result<()> {
+    /// exchange the information of both nodes
+    pub async fn hello(&mut self) -> Result<PeerInfo> {
+        let local = PeerInfo::local(self.node.addr(), &*self.host.lock().await);
+        let res = self.send(Message {
+            method: Method::Hello,
+            payload: Payload::Peer(Box::new(local)),
+        }).await?;
+
+        match res.result {
+            Some(Payload::Peer(info)) => Ok(*info),
+            _ => Err(anyhow!("no peer information")),
+        }
+    }
+
+    pub async fn send_notification(&mut self, mut notif: Notification) -> Result<()> {
+        notif.origin = Some(self.node.addr());
+
+        // only the hash is sent, the host asks for the icon when it does not have it yet
+        if let Some(icon) = notif.icon.take()
+            && let Err(e) = icon::cache_icon(&icon)
+        {
+            warn!("failed to cache icon, sending the notification without it: {:?}", e);
+            notif.icon_hash = None;
+        }
+
         self.send(
                 Message {
                     method: Method::NewNotification,
-                    payload: Payload::Notification(notif),
+                    payload: Payload::Notification(Box::new(notif)),
                 }
             )
             .await?;
 
+        metrics::FORWARDED.inc();
         Ok(())
     }
 
@@ -105,8 +180,10 @@ impl StreamClient {
         }
 
         if let Some(Payload::Address(a, b, c, d, p)) = res.result {
-            println!("get host addr: {}.{}.{}.{}:{}", a, b, c, d, p);
-            *self.host.lock().await = AppMode::Client(Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), p)));
+            info!("get host addr: {}.{}.{}.{}:{}", a, b, c, d, p);
+            let mut host = self.host.lock().await;
+            *host = AppMode::Client(Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), p)));
+            events::host_changed(&host);
         }
 
         Ok(())
@@ -121,6 +198,50 @@ impl StreamClient {
 
         Ok(())
     }
+
+    pub async fn invoke_action(&mut self, notification_id: NotificationId, action_key: String) -> Result<()> {
+        self.send(Message {
+            method: Method::InvokeAction { notification_id, action_key },
+            payload: Payload::Empty,
+        }).await?;
+
+        Ok(())
+    }
+
+    pub async fn reply(&mut self, notification_id: NotificationId, text: String) -> Result<()> {
+        self.send(Message {
+            method: Method::Reply { notification_id, text },
+            payload: Payload::Empty,
+        }).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_icon(&mut self, hash: String) -> Result<Vec<u8>> {
+        let res = self.send(Message {
+            method: Method::GetIcon { hash: hash.clone() },
+            payload: Payload::Empty,
+        }).await?;
+
+        let Some(Payload::Raw(icon)) = res.result else {
+            return Err(anyhow!("failed to get icon {}", hash));
+        };
+
+        if icon::icon_hash(&icon) != hash {
+            return Err(anyhow!("icon {} does not match its hash", hash));
+        }
+
+        Ok(icon)
+    }
+
+    pub async fn notification_closed(&mut self, notification_id: NotificationId) -> Result<()> {
+        self.send(Message {
+            method: Method::NotificationClosed { notification_id },
+            payload: Payload::Empty,
+        }).await?;
+
+        Ok(())
+    }
 }
 
 pub struct MessageHandler {
@@ -129,18 +250,21 @@ pub struct MessageHandler {
 }
 
 impl MessageHandler {
-    pub async fn handle(&self, msg: Message) -> Response {
+    /// `from` is the address the message came from
+    pub async fn handle(&self, msg: Message, from: Option<SocketAddr>) -> Response {
         let res: Result<Response> = {
             match msg.method {
                 Method::Ping => {
                     Ok(Response::success(Payload::Text("Pong".to_string())))
                 },
                 Method::NewNotification => {
-                    println!("new notification {:?}", msg.payload);
-                    if self.host.lock().await.is_host() {
-                        if let Payload::Notification(notif) = msg.payload {
-                            crate::notification::send_notification(notif).await;
-                        }
+                    debug!("new notification {:?}", msg.payload);
+                    if self.host.lock().await.is_host()
+                        && let Payload::Notification(notif) = msg.payload
+                    {
+                        // the source device is waiting for this response, fetching the icon
+                        // from it has to happen after replying
+                        self.enqueue(from, Inbound::Notification(notif));
                     }
 
                     Ok(Response::empty())
@@ -163,16 +287,105 @@ impl MessageHandler {
                 Method::ImHost => {
                     let mut host = self.host.lock().await;
                     if let Some(addr) = host.get_host() {
-                        println!("change host to client");
+                        info!("change host to client");
                         *host = AppMode::Client(Some(*addr));
+                        events::host_changed(&host);
                     };
 
                     Ok(Response::empty())
                 },
+                Method::InvokeAction { notification_id, action_key } => {
+                    info!("invoke action {} of notification {}", action_key, notification_id);
+                    crate::notification::invoke_action(notification_id, action_key).await;
+                    Ok(Response::empty())
+                },
+                Method::Reply { notification_id, text } => {
+                    info!("reply to notification {}", notification_id);
+                    crate::notification::reply(notification_id, text).await;
+                    Ok(Response::empty())
+                },
+                Method::GetIcon { hash } => {
+                    icon::load_cached_icon(&hash).map(|icon| Response::success(Payload::Raw(icon)))
+                },
+                Method::NotificationClosed { notification_id } => {
+                    info!("notification {} closed", notification_id);
+                    self.enqueue(from, Inbound::Closed(notification_id));
+                    Ok(Response::empty())
+                },
+                Method::Hello => {
+                    // the node tells the address it listens on, only believed when it connects from that ip
+                    if let Payload::Peer(info) = msg.payload {
+                        match from {
+                            Some(from) if from.ip() == info.addr.ip() => peers::seen(info.addr, Some(*info)).await,
+                            _ => warn!("{:?} claims to listen on {}, not recorded", from, info.addr),
+                        }
+                    }
+
+                    let local = PeerInfo::local(self.node.addr(), &*self.host.lock().await);
+                    Ok(Response::success(Payload::Peer(Box::new(local))))
+                },
                 _ => Ok(Response::empty()),
             }
         };
 
         res.unwrap_or(Response::failed())
     }
+
+    /// notifications and closes of a device are handled one after the other, so a close or a
+    /// replacement never overtakes a notification still fetching its icon
+    fn enqueue(&self, from: Option<SocketAddr>, inbound: Inbound) {
+        let source = from.map(|from| from.ip());
+        let mut inboxes = INBOXES.lock().unwrap_or_else(|e| e.into_inner());
+        let inbound = match inboxes.get(&source) {
+            Some(tx) => match tx.send(inbound) {
+                Ok(()) => return,
+                Err(e) => e.0,
+            },
+            None => inbound,
+        };
+
+        let (tx, mut rx) = unbounded_channel();
+        let client = Client::new(self.node.clone(), self.host.clone());
+        tokio::spawn(async move {
+            while let Some(inbound) = rx.recv().await {
+                match inbound {
+                    Inbound::Notification(notif) => receive_notification(&client, *notif).await,
+                    Inbound::Closed(id) => crate::notification::close_notification(id).await,
+                }
+            }
+        });
+
+        let _ = tx.send(inbound);
+        inboxes.insert(source, tx);
+    }
+}
+
+enum Inbound {
+    Notification(Box<Notification>),
+    Closed(NotificationId),
+}
+
+/// the queue of each source device
+static INBOXES: LazyLock<std::sync::Mutex<HashMap<Option<IpAddr>, UnboundedSender<Inbound>>>> =
+    LazyLock::new(Default::default);
+
+async fn receive_notification(client: &Client, notif: Notification) {
+    if let (Some(hash), Some(origin)) = (&notif.icon_hash, notif.origin)
+        && icon::cached_icon(hash).is_none()
+    {
+        match fetch_icon(client, origin, hash.clone()).await {
+            Ok(icon) => {
+                if let Err(e) = icon::cache_icon(&icon) {
+                    warn!("failed to cache icon {}: {:?}", hash, e);
+                }
+            },
+            Err(e) => warn!("failed to fetch icon {} from {}: {:?}", hash, origin, e),
+        }
+    }
+
+    crate::notification::sink::dispatch(notif).await;
+}
+
+async fn fetch_icon(client: &Client, origin: SocketAddr, hash: String) -> Result<Vec<u8>> {
+    client.connect(origin).await?.get_icon(hash).await
 }
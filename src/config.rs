@@ -0,0 +1,307 @@
+use std::{collections::HashMap, fs, io::ErrorKind, net::SocketAddr, path::PathBuf};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::{notification::Urgency, DIRS};
+
+/// settings read from `config.toml` in the config dir, every field is optional
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub capture: CaptureConfig,
+    pub sources: Vec<SourceConfig>,
+    pub sinks: Vec<SinkConfig>,
+    pub attachments: AttachmentConfig,
+    pub http: HttpConfig,
+    pub mqtt: MqttConfig,
+    pub log: LogConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capture: CaptureConfig::default(),
+            sources: vec![SourceConfig::System],
+            sinks: vec![SinkConfig {
+                kind: SinkKind::Desktop,
+                filter: SinkFilter::default(),
+            }],
+            attachments: AttachmentConfig::default(),
+            http: HttpConfig::default(),
+            mqtt: MqttConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+/// where the notifications captured on this node come from, every source runs at the same time
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceConfig {
+    /// the desktop notifications
+    System,
+    /// one JSON notification per line on stdin
+    Stdin,
+    /// JSON notifications appended line by line to a file
+    File { path: PathBuf },
+    /// a test notification every `interval` seconds
+    Test {
+        #[serde(default = "default_test_interval")]
+        interval: u64,
+    },
+}
+
+fn default_test_interval() -> u64 {
+    30
+}
+
+/// where the host outputs the notifications it receives, every sink runs at the same time
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    #[serde(default)]
+    pub filter: SinkFilter,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkKind {
+    /// the desktop popup
+    Desktop,
+    /// JSON lines appended to a file, `notifications.log` in the data dir by default
+    Log { path: Option<PathBuf> },
+    /// JSON lines written to every client of a unix socket, `gon.sock` in the runtime dir by default
+    Ipc { path: Option<PathBuf> },
+    /// an HTTP request per notification
+    Webhook(WebhookConfig),
+    /// a command run per notification
+    Exec(ExecConfig),
+    /// a message per notification through the MQTT bridge
+    Mqtt,
+    /// an audit record per notification in the systemd journal or syslog
+    Audit {
+        #[serde(default)]
+        target: AuditTarget,
+        /// `/run/systemd/journal/socket` or `/dev/log` by default
+        socket: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditTarget {
+    #[default]
+    Journald,
+    Syslog,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_webhook_method")]
+    pub method: String,
+    /// added to every request, `Content-Type` is `application/json` unless set here
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// request body with `{{field}}` placeholders for the fields of the JSON record, the record itself when unset
+    pub template: Option<String>,
+    /// attempts after the first one, for network errors, 5xx and 429 responses
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    /// milliseconds before the first retry, doubled on every retry up to 5 minutes
+    #[serde(default = "default_webhook_backoff")]
+    pub backoff: u64,
+    /// seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// undelivered notifications are appended here, `webhook-dead-letter.log` in the data dir by default
+    pub dead_letter: Option<PathBuf>,
+}
+
+fn default_webhook_method() -> String {
+    "POST".to_string()
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_backoff() -> u64 {
+    1000
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecConfig {
+    /// program and its arguments, not run through a shell
+    pub command: Vec<String>,
+    /// seconds before the command is killed
+    #[serde(default = "default_exec_timeout")]
+    pub timeout: u64,
+    /// commands running at the same time
+    #[serde(default = "default_exec_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_exec_timeout() -> u64 {
+    30
+}
+
+fn default_exec_concurrency() -> usize {
+    4
+}
+
+/// notifications a sink receives, an empty list matches everything
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SinkFilter {
+    /// app names or ids
+    pub apps: Vec<String>,
+    pub exclude_apps: Vec<String>,
+    /// ids of the devices which captured the notification
+    pub devices: Vec<String>,
+    pub min_urgency: Option<Urgency>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub mode: CaptureMode,
+}
+
+/// how notifications are captured on Linux
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// eavesdrop the `Notify` calls, the bus has to allow monitoring,
+    /// actions and inline replies are not forwarded as applications only listen to the notification server
+    #[default]
+    Monitor,
+    /// own `org.freedesktop.Notifications`, nothing is displayed on this device
+    Server,
+    /// replace the running notification server and pass every call through to it
+    Proxy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AttachmentConfig {
+    /// forward images attached to notifications
+    pub enabled: bool,
+    /// attachments are scaled down to fit in a square of this size
+    pub max_dimension: u32,
+    /// attachments larger than this once encoded are dropped
+    pub max_bytes: usize,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_dimension: 512,
+            max_bytes: 512 * 1024,
+        }
+    }
+}
+
+/// the HTTP API, see the README for the endpoints
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+    /// required as `Authorization: Bearer <token>` when set, and to listen on other addresses than loopback
+    pub token: Option<String>,
+    /// notifications kept for `GET /history`
+    pub history: usize,
+    /// accept the publish requests of ntfy clients
+    pub ntfy: bool,
+    /// accept the `POST /message` requests of Gotify clients
+    pub gotify: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 8787)),
+            token: None,
+            history: 100,
+            ntfy: false,
+            gotify: false,
+        }
+    }
+}
+
+/// the MQTT bridge, notifications are published by the `mqtt` sink
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// `gon-<device id>` when unset
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// notifications are published to `<prefix>/<device>/<app>`
+    pub topic_prefix: String,
+    /// JSON notifications published here are injected like `POST /notify`
+    pub command_topic: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: None,
+            username: None,
+            password: None,
+            topic_prefix: "gon".to_string(),
+            command_topic: "gon/notify".to_string(),
+            qos: 1,
+            retain: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// default level and levels by module, like `warn,gon=info,gon::daemon=debug`, `GON_LOG` overrides it
+    pub filter: String,
+    /// one JSON object per line instead of text
+    pub json: bool,
+    /// also write to `gon.log` in the data dir
+    pub file: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "warn,gon=info".to_string(),
+            json: false,
+            file: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let path = DIRS.config_dir().join("config.toml");
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
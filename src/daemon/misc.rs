@@ -1,31 +1,81 @@
-use std::net::Ipv4Addr;
+use std::{fs, net::Ipv4Addr};
 
 use anyhow::Result;
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use sha2::{Digest, Sha256};
+
+use crate::{DEVICE_ID, DIRS};
 
 pub fn get_preferred_local_ip() -> Result<Ipv4Addr> {
     let interfaces = if_addrs::get_if_addrs()?;
 
     for iface in &interfaces {
-        if !iface.is_loopback() {
-            if let if_addrs::IfAddr::V4(ref addr) = iface.addr {
-                let ip = addr.ip;
-                if (ip.octets()[0] == 192 && ip.octets()[1] == 168) ||                     // 192.168.x.x
-                   (ip.octets()[0] == 10) ||                                               // 10.x.x.x
-                   (ip.octets()[0] == 172 && ip.octets()[1] >= 16 && ip.octets()[1] <= 31) // 172.16.x.x - 172.31.x.x
-                {
-                    return Ok(ip);
-                }
+        if !iface.is_loopback()
+            && let if_addrs::IfAddr::V4(ref addr) = iface.addr
+        {
+            let ip = addr.ip;
+            if (ip.octets()[0] == 192 && ip.octets()[1] == 168) ||                     // 192.168.x.x
+               (ip.octets()[0] == 10) ||                                               // 10.x.x.x
+               (ip.octets()[0] == 172 && ip.octets()[1] >= 16 && ip.octets()[1] <= 31) // 172.16.x.x - 172.31.x.x
+            {
+                return Ok(ip);
             }
         }
     }
 
     for iface in &interfaces {
-        if !iface.is_loopback() {
-            if let if_addrs::IfAddr::V4(ref addr) = iface.addr {
-                return Ok(addr.ip);
-            }
+        if !iface.is_loopback()
+            && let if_addrs::IfAddr::V4(ref addr) = iface.addr
+        {
+            return Ok(addr.ip);
         }
     }
 
     Ok(Ipv4Addr::new(127, 0, 0, 1))
 }
+
+/// random id of this device, generated once and kept in the config dir
+pub fn load_device_id() -> Result<String> {
+    let dir = DIRS.config_dir();
+    fs::create_dir_all(dir)?;
+
+    let path = dir.join("device_id");
+    if let Ok(id) = fs::read_to_string(&path) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+
+    let id = random_device_id();
+    fs::write(path, &id)?;
+
+    Ok(id)
+}
+
+pub fn random_device_id() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+/// lowercase hex of the bytes
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// hex encoded sha256 of the data
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// name of this device shown to the other nodes, the host name when there is one
+pub fn device_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEVICE_ID.clone())
+}
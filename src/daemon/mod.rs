@@ -2,5 +2,5 @@ pub mod service;
 pub mod node;
 pub mod protocol;
 
-mod misc;
+pub mod misc;
 
@@ -1,23 +1,30 @@
-use std::{fs, marker::PhantomData, net::{IpAddr, SocketAddr}, sync::Arc};
+use std::{fs, marker::PhantomData, net::{IpAddr, SocketAddr}, sync::{Arc, PoisonError, RwLock}};
 
 use anyhow::{Result, anyhow};
+use log::{debug, error, info};
 use chacha20poly1305::{
     aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit, OsRng}, ChaCha20Poly1305, Nonce
 };
+use sha2::{Digest, Sha256};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    select,
     sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
     task::JoinHandle,
 };
 
-use crate::{daemon::misc::get_preferred_local_ip, DIRS};
+use crate::{daemon::misc::{self, get_preferred_local_ip}, metrics, DIRS};
 
-use super::protocol::Message;
+use super::protocol::{Message, Method};
 
 pub struct Node<R> {
-    pub addr: SocketAddr,
+    /// address advertised to the other nodes, it follows the address of the device
+    addr: RwLock<SocketAddr>,
     socket: Option<TcpListener>,
+    /// listeners bound on the new address of the device, taken over by the accept loop
+    rebind_tx: UnboundedSender<TcpListener>,
+    rebind_rx: Option<UnboundedReceiver<TcpListener>>,
     codec: Arc<NodeMessageCodec>,
     _phamtom_response: PhantomData<R>,
 }
@@ -25,15 +32,18 @@ pub struct Node<R> {
 impl<R> Node<R> {
     pub async fn new() -> Result<Self> {
         let local_addr = get_preferred_local_ip()?;
-        let socket = TcpListener::bind(format!("{}:0", local_addr)).await?;
+        let socket = TcpListener::bind((local_addr, 0)).await?;
         let addr = socket.local_addr()?;
 
         let codec = NodeMessageCodec::new()?;
+        let (rebind_tx, rebind_rx) = unbounded_channel();
 
         let node = Self {
-            addr,
+            addr: RwLock::new(addr),
             codec: Arc::new(codec),
             socket: Some(socket),
+            rebind_tx,
+            rebind_rx: Some(rebind_rx),
             _phamtom_response: PhantomData,
         };
 
@@ -42,18 +52,27 @@ impl<R> Node<R> {
 
     #[allow(clippy::let_underscore_future)]
     pub async fn listen(&mut self) -> Result<UnboundedReceiver<(TcpStream, Message)>> {
-        let listener = self.socket.take().unwrap();
+        let mut listener = self.socket.take().unwrap();
+        let mut rebind_rx = self.rebind_rx.take().unwrap();
         let codec = self.codec.clone();
         let (tx, rx) = unbounded_channel();
 
         let _: JoinHandle<Result<()>> = tokio::spawn(async move {
             loop {
-                let Ok((socket, addr)) = listener.accept().await else {
-                    println!("error accepting client");
+                let accepted = select! {
+                    accepted = listener.accept() => accepted,
+                    Some(rebound) = rebind_rx.recv() => {
+                        listener = rebound;
+                        continue;
+                    },
+                };
+
+                let Ok((socket, addr)) = accepted else {
+                    error!("error accepting client");
                     continue;
                 };
 
-                println!("New client connected: {}", addr);
+                debug!("New client connected: {}", addr);
 
                 let codec = codec.clone();
                 let tx = tx.clone();
@@ -61,7 +80,8 @@ impl<R> Node<R> {
                 // Spawn a new task for each client
                 tokio::spawn(async move {
                     if let Err(e) = Self::handle_client(socket, codec, tx).await {
-                        eprintln!("Error handling client: {}", e);
+                        metrics::CONNECTION_ERRORS.inc();
+                        error!("Error handling client: {}", e);
                     }
                 });
             }
@@ -79,6 +99,7 @@ impl<R> Node<R> {
         let mut len_bytes = [0u8; 4];
         stream.read_exact(&mut len_bytes).await?;
         let msg_len = u32::from_be_bytes(len_bytes) as usize;
+        metrics::FRAMES_RECEIVED.observe(msg_len as u64);
 
         // Read message content based on length
         let mut buffer = vec![0u8; msg_len];
@@ -91,9 +112,53 @@ impl<R> Node<R> {
         Ok(())
     }
 
+    pub fn key_fingerprint(&self) -> &str {
+        &self.codec.fingerprint
+    }
+
+    /// encrypt and decrypt a frame with the key of this node
+    pub fn self_test(&self) -> Result<()> {
+        let frame = self.codec.encode(&Method::Ping)?;
+        match self.codec.decode::<Method>(&frame)? {
+            Method::Ping => Ok(()),
+            _ => Err(anyhow!("test frame changed")),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        *self.addr.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// follow the preferred address of the device, listening on it from now on,
+    /// the new address is returned when it changed
+    pub async fn refresh_addr(&self) -> Result<Option<SocketAddr>> {
+        let Ok(ip) = get_preferred_local_ip() else {
+            return Ok(None);
+        };
+
+        let old = self.addr();
+        if old.ip() == IpAddr::V4(ip) {
+            return Ok(None);
+        }
+
+        // keep the port when it is free on the new address
+        let listener = match TcpListener::bind((ip, old.port())).await {
+            Ok(listener) => listener,
+            Err(_) => TcpListener::bind((ip, 0)).await?,
+        };
+
+        let addr = listener.local_addr()?;
+        self.rebind_tx.send(listener)?;
+        *self.addr.write().unwrap_or_else(PoisonError::into_inner) = addr;
+        info!("listen on {} instead of {}", addr, old);
+
+        Ok(Some(addr))
+    }
+
     pub fn get_addr_v4(&self) -> Option<(u8, u8, u8, u8, u16)> {
-        let ip = self.addr.ip();
-        let port = self.addr.port();
+        let addr = self.addr();
+        let ip = addr.ip();
+        let port = addr.port();
         let ip = match ip {
             IpAddr::V4(ip) => ip.octets(),
             IpAddr::V6(_) => return None,
@@ -121,6 +186,7 @@ where
         let mut len_bytes = [0u8; 4];
         stream.read_exact(&mut len_bytes).await?;
         let response_len = u32::from_be_bytes(len_bytes) as usize;
+        metrics::FRAMES_RECEIVED.observe(response_len as u64);
 
         // Read response data
         let mut buffer = vec![0u8; response_len];
@@ -136,6 +202,7 @@ where
         T: for<'de> serde::Serialize
     {
         let serialized = self.codec.encode(&data)?;
+        metrics::FRAMES_SENT.observe(serialized.len() as u64);
 
         // Send length prefix (4 bytes) followed by serialized data
         stream
@@ -149,6 +216,8 @@ where
 struct NodeMessageCodec {
     cipher: ChaCha20Poly1305,
     nonce: Nonce,
+    /// start of the sha256 of the key, to compare keys between devices without showing them
+    fingerprint: String,
 }
 
 impl NodeMessageCodec {
@@ -172,7 +241,7 @@ impl NodeMessageCodec {
                 Some((password, cipher))
             })
             .unwrap_or_else(|| {
-                eprintln!("no key found, generate new one");
+                info!("no key found, generate new one");
                 let key = ChaCha20Poly1305::generate_key(&mut OsRng);
                 (key.to_vec(), ChaCha20Poly1305::new(&key))
             });
@@ -181,10 +250,17 @@ impl NodeMessageCodec {
             fs::write(nonce_path, nonce.as_slice())?;
         }
         if !matches!(fs::exists(&key_path), Ok(true)) {
-            fs::write(key_path, key)?;
+            fs::write(key_path, &key)?;
         }
 
-        Ok(Self { cipher, nonce })
+        let fingerprint = Sha256::digest(&key)
+            .iter()
+            .take(8)
+            .map(|b| misc::hex(&[*b]))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Ok(Self { cipher, nonce, fingerprint })
     }
 
     fn encode<T>(&self, message: &T) -> Result<Vec<u8>>
@@ -214,6 +290,9 @@ impl NodeMessageCodec {
     fn decrypt(&self, msg: &[u8]) -> Result<Vec<u8>> {
         self.cipher
             .decrypt(&self.nonce, msg)
-            .map_err(|_| anyhow!("decrypt fail"))
+            .map_err(|_| {
+                metrics::DECRYPT_FAILURES.inc();
+                anyhow!("decrypt fail")
+            })
     }
 }
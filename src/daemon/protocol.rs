@@ -2,7 +2,13 @@ use std::{collections::HashMap, net::{IpAddr, SocketAddr}};
 
 use serde::{Deserialize, Serialize};
 
-use crate::notification::Notification;
+use crate::{
+    notification::{Notification, NotificationId},
+    peers::PeerInfo,
+};
+
+/// version of the messages between nodes, nodes speaking another one are not contacted
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Method {
@@ -11,6 +17,26 @@ pub enum Method {
     NewNotification,
     GetHost,
     ImHost,
+    InvokeAction {
+        notification_id: NotificationId,
+        action_key: String,
+    },
+    Reply {
+        notification_id: NotificationId,
+        text: String,
+    },
+    /// sent by the host to the source device when the icon of a notification is not cached
+    GetIcon {
+        hash: String,
+    },
+    /// sent both ways, from the source device to the host and back
+    NotificationClosed {
+        notification_id: NotificationId,
+    },
+    /// asked by `gon status` and `gon doctor`, answered with a JSON `StatusReport` as text
+    Status,
+    /// exchange of `PeerInfo`, answered whatever the role
+    Hello,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,6 +49,18 @@ impl Message {
     pub fn is_done(&self) -> bool {
         self.method == Method::Done
     }
+
+    /// messages sent by the host back to the source device of a notification,
+    /// they are handled whatever the role of this node is
+    pub fn is_callback(&self) -> bool {
+        matches!(
+            self.method,
+            Method::InvokeAction { .. }
+                | Method::Reply { .. }
+                | Method::GetIcon { .. }
+                | Method::NotificationClosed { .. }
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -32,7 +70,8 @@ pub enum Payload {
     List(Vec<String>),
     Dictionary(HashMap<String, String>),
     Raw(Vec<u8>),
-    Notification(Notification),
+    Notification(Box<Notification>),
+    Peer(Box<PeerInfo>),
     Address(u8, u8, u8, u8, u16),
     Empty,
 }
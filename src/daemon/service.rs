@@ -1,58 +1,193 @@
 use std::{collections::HashMap, net::SocketAddr};
 use anyhow::{anyhow, Result};
-use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+use log::{info, warn};
+use mdns_sd::{DaemonStatus, Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use super::{misc::device_name, protocol::PROTOCOL_VERSION};
+use crate::{AppMode, DEVICE_ID};
 
 const DOMAIN: &str = "_gon._tcp.local.";
 const SERVICE_NAME: &str = "Gate of Notification";
-const HOSTNAME: &str = "gon.local.";
+
+/// what a node advertises in the TXT record of its service
+#[derive(Debug, Clone)]
+pub struct NodeProperties {
+    pub device_id: String,
+    pub name: String,
+    pub protocol: u32,
+    pub role: String,
+    pub fingerprint: String,
+}
+
+impl NodeProperties {
+    pub fn local(fingerprint: &str, mode: &AppMode<SocketAddr>) -> Self {
+        Self {
+            device_id: DEVICE_ID.clone(),
+            name: device_name(),
+            protocol: PROTOCOL_VERSION,
+            role: if mode.is_host() { "host" } else { "client" }.to_string(),
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.role == "host"
+    }
+
+    fn to_txt(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("id".to_string(), self.device_id.clone()),
+            ("name".to_string(), self.name.clone()),
+            ("proto".to_string(), self.protocol.to_string()),
+            ("role".to_string(), self.role.clone()),
+            ("fp".to_string(), self.fingerprint.clone()),
+        ])
+    }
+
+    /// None for nodes which do not advertise their properties
+    fn from_info(info: &ServiceInfo) -> Option<Self> {
+        Some(Self {
+            device_id: info.get_property_val_str("id")?.to_string(),
+            name: info.get_property_val_str("name")?.to_string(),
+            protocol: info.get_property_val_str("proto")?.parse().ok()?,
+            role: info.get_property_val_str("role")?.to_string(),
+            fingerprint: info.get_property_val_str("fp")?.to_string(),
+        })
+    }
+}
 
 pub enum AppServiceEvent {
     None,
-    NodeDiscoverd(SocketAddr),
+    NodeDiscoverd(SocketAddr, Option<NodeProperties>),
+    /// a node withdrew its service or its record expired
+    NodeLost(SocketAddr),
 }
 
 pub struct AppService {
-    _mdns_daemon: ServiceDaemon,
+    mdns_daemon: ServiceDaemon,
     mdns_rx: Receiver<ServiceEvent>,
     addr: SocketAddr,
+    properties: NodeProperties,
+    fullname: String,
+    /// address of every resolved service, removals only tell the name
+    resolved: HashMap<String, SocketAddr>,
+    /// old addresses of services resolved again on another address, reported by the next calls
+    moved: Vec<SocketAddr>,
 }
 
 impl AppService {
-    pub fn new(addr: SocketAddr) -> Result<Self> {
+    pub fn new(addr: SocketAddr, properties: NodeProperties) -> Result<Self> {
         let mdns = ServiceDaemon::new()?;
 
-        let service_info = ServiceInfo::new(
-            DOMAIN,
-            SERVICE_NAME,
-            HOSTNAME,
-            addr.ip().to_string(),
-            addr.port(),
-            Some(HashMap::new()),
-        )?;
-
+        let service_info = Self::service_info(addr, &properties)?;
+        let fullname = service_info.get_fullname().to_string();
         mdns.register(service_info)?;
         let mdns_rx = mdns.browse(DOMAIN)?;
-        println!("services are registered on mdns and start browse other gon service on {}", addr);
+        info!("services are registered on mdns and start browse other gon service on {}", addr);
 
         Ok(Self {
             addr,
             mdns_rx,
-            _mdns_daemon: mdns,
+            mdns_daemon: mdns,
+            properties,
+            fullname,
+            resolved: HashMap::new(),
+            moved: Vec::new(),
         })
     }
 
+    /// every node has its own instance and host name, the device id keeps them apart
+    fn service_info(addr: SocketAddr, properties: &NodeProperties) -> Result<ServiceInfo> {
+        Ok(ServiceInfo::new(
+            DOMAIN,
+            &format!("{} {}", SERVICE_NAME, properties.device_id),
+            &format!("gon-{}.local.", properties.device_id),
+            addr.ip().to_string(),
+            addr.port(),
+            Some(properties.to_txt()),
+        )?)
+    }
+
+    /// announce the new role of this node
+    pub fn set_role(&mut self, role: &str) -> Result<()> {
+        if self.properties.role == role {
+            return Ok(());
+        }
+
+        self.properties.role = role.to_string();
+        self.mdns_daemon.register(Self::service_info(self.addr, &self.properties)?)?;
+        info!("announce role {} on mdns", role);
+
+        Ok(())
+    }
+
+    /// announce the service on the new address of this node, withdrawing it first
+    /// so the other nodes forget the old address instead of waiting for its record to expire
+    pub async fn set_addr(&mut self, addr: SocketAddr) -> Result<()> {
+        if let Err(e) = self.unregister().await {
+            warn!("failed to withdraw the service from {}: {:?}", self.addr, e);
+        }
+
+        self.addr = addr;
+        self.mdns_daemon.register(Self::service_info(self.addr, &self.properties)?)?;
+        info!("services are registered on mdns again on {}", addr);
+
+        Ok(())
+    }
+
+    /// withdraw the service, the other nodes see this node leave right away
+    pub async fn unregister(&self) -> Result<()> {
+        self.mdns_daemon.unregister(&self.fullname)?.recv_async().await?;
+        Ok(())
+    }
+
+    pub fn fullname(&self) -> &str {
+        &self.fullname
+    }
+
+    /// the status is asked right away, the answer can be awaited without holding the service
+    pub fn is_running(&self) -> impl Future<Output = bool> + use<> {
+        let status = self.mdns_daemon.status();
+        async move {
+            match status {
+                Ok(status) => matches!(status.recv_async().await, Ok(DaemonStatus::Running)),
+                Err(_) => false,
+            }
+        }
+    }
+
     pub async fn next(&mut self) -> Result<AppServiceEvent> {
+        if let Some(addr) = self.moved.pop() {
+            return Ok(AppServiceEvent::NodeLost(addr));
+        }
+
         let mut event = AppServiceEvent::None;
-        if let Ok(ServiceEvent::ServiceResolved(info)) = self.mdns_rx.recv_async().await {
-            if info.get_type().eq(DOMAIN) {
+        match self.mdns_rx.recv_async().await {
+            Ok(ServiceEvent::ServiceResolved(info)) if info.get_type().eq(DOMAIN) => {
                 let addr = info.get_addresses().iter().next().ok_or(anyhow!("empty address recive from mdns"))?;
                 let port = info.get_port();
+                let properties = NodeProperties::from_info(&info);
+
+                let is_self = properties.as_ref().is_some_and(|p| p.device_id == self.properties.device_id);
+                if !is_self && (*addr != self.addr.ip() || port != self.addr.port()) {
+                    let addr = SocketAddr::new(*addr, port);
+                    // the withdrawal of the old address may have been missed
+                    if let Some(old) = self.resolved.insert(info.get_fullname().to_string(), addr)
+                        && old != addr
+                    {
+                        self.moved.push(old);
+                    }
 
-                if *addr != self.addr.ip() || (*addr == self.addr.ip() && port != self.addr.port()) {
-                    event = AppServiceEvent::NodeDiscoverd(SocketAddr::new(*addr, port));
+                    event = AppServiceEvent::NodeDiscoverd(addr, properties);
                 }
-            }
-        };
+            },
+            Ok(ServiceEvent::ServiceRemoved(ty, fullname)) if ty.eq(DOMAIN) => {
+                if let Some(addr) = self.resolved.remove(&fullname) {
+                    event = AppServiceEvent::NodeLost(addr);
+                }
+            },
+            _ => {},
+        }
 
         Ok(event)
     }
@@ -0,0 +1,93 @@
+use std::{net::SocketAddr, sync::LazyLock};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{notification::sink::NotificationRecord, AppMode};
+
+/// events kept for subscribers which fall behind
+const EVENTS_BUFFER: usize = 256;
+
+/// what happens on this node, for the live event stream of the HTTP API
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+    /// a notification received by the host
+    Notification(NotificationRecord),
+    HostChanged {
+        role: &'static str,
+        host: Option<SocketAddr>,
+    },
+    PeerDiscovered { addr: SocketAddr },
+    /// a peer stopped answering or left the network
+    PeerLost { addr: SocketAddr },
+}
+
+impl NodeEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeEvent::Notification(_) => "notification",
+            NodeEvent::HostChanged { .. } => "host_changed",
+            NodeEvent::PeerDiscovered { .. } => "peer_discovered",
+            NodeEvent::PeerLost { .. } => "peer_lost",
+        }
+    }
+}
+
+static EVENTS: LazyLock<broadcast::Sender<NodeEvent>> = LazyLock::new(|| broadcast::channel(EVENTS_BUFFER).0);
+
+pub fn emit(event: NodeEvent) {
+    // nobody listening is fine
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<NodeEvent> {
+    EVENTS.subscribe()
+}
+
+pub fn host_changed(mode: &AppMode<SocketAddr>) {
+    emit(NodeEvent::HostChanged {
+        role: if mode.is_host() { "host" } else { "client" },
+        host: mode.get_host().copied(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use serde_json::json;
+
+    use super::{subscribe, NodeEvent};
+    use crate::peers;
+
+    #[test]
+    fn serializes_peer_events() {
+        let addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let event = NodeEvent::PeerLost { addr };
+
+        assert_eq!(event.name(), "peer_lost");
+        assert_eq!(serde_json::to_value(&event).unwrap(), json!({"event": "peer_lost", "addr": "192.0.2.1:4000"}));
+    }
+
+    #[tokio::test]
+    async fn emits_peer_discovered_and_lost() {
+        let addr: SocketAddr = "192.0.2.2:4000".parse().unwrap();
+        let mut events = subscribe();
+
+        peers::seen(addr, None).await;
+        peers::remove(addr).await;
+
+        let mut names = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NodeEvent::PeerDiscovered { addr: a } | NodeEvent::PeerLost { addr: a } if a == addr => {
+                    names.push(event.name())
+                },
+                _ => {},
+            }
+        }
+
+        assert_eq!(names, ["peer_discovered", "peer_lost"]);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{FromRequest, Multipart, Request, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Form, Json, Router,
+};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+
+use crate::notification::{Notification, Urgency};
+
+use super::{deliver, ApiError, ApiState};
+
+/// the `POST /message` API of Gotify, the application token is checked like the gon token
+pub fn routes() -> Router<ApiState> {
+    Router::new().route("/message", post(message))
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    title: Option<String>,
+    message: String,
+    /// 0 to 10, Gotify apps treat 8 and above as high
+    #[serde(default = "default_priority", deserialize_with = "clamped_priority")]
+    priority: u8,
+}
+
+fn default_priority() -> u8 {
+    5
+}
+
+/// Gotify takes any number, the ones outside 0 to 10 are brought back in
+fn clamped_priority<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    Ok(clamp_priority(i64::deserialize(deserializer)?))
+}
+
+fn clamp_priority(priority: i64) -> u8 {
+    priority.clamp(0, 10) as u8
+}
+
+/// Gotify clients send JSON, a multipart form (`curl -F`) or an urlencoded form
+async fn message(State(state): State<ApiState>, request: Request) -> Result<impl IntoResponse, ApiError> {
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let msg = if content_type.starts_with("application/json") {
+        Json::<Message>::from_request(request, &())
+            .await
+            .map(|Json(msg)| msg)
+            .map_err(|e| ApiError(e.status(), e.body_text()))?
+    } else if content_type.starts_with("multipart/form-data") {
+        let multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|e| ApiError(e.status(), e.body_text()))?;
+        from_multipart(multipart).await?
+    } else {
+        Form::<Message>::from_request(request, &())
+            .await
+            .map(|Form(msg)| msg)
+            .map_err(|e| ApiError(e.status(), e.body_text()))?
+    };
+
+    let title = msg.title.clone().unwrap_or_else(|| "Gotify".to_string());
+    let mut notification = Notification::new("gotify".to_string(), title, msg.message.clone());
+    notification.urgency = urgency(msg.priority);
+
+    let id = deliver(&state, notification).await?;
+    Ok(Json(json!({
+        "id": id.seq,
+        "appid": 0,
+        "title": msg.title,
+        "message": msg.message,
+        "priority": msg.priority,
+    })))
+}
+
+async fn from_multipart(mut multipart: Multipart) -> Result<Message, ApiError> {
+    let mut fields = HashMap::new();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ApiError(e.status(), e.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        let value = field.text().await.map_err(|e| ApiError(e.status(), e.body_text()))?;
+        fields.insert(name, value);
+    }
+
+    Ok(Message {
+        title: fields.remove("title"),
+        message: fields
+            .remove("message")
+            .ok_or(ApiError(StatusCode::BAD_REQUEST, "missing message".to_string()))?,
+        priority: fields
+            .get("priority")
+            .and_then(|priority| priority.trim().parse().ok())
+            .map(clamp_priority)
+            .unwrap_or_else(default_priority),
+    })
+}
+
+fn urgency(priority: u8) -> Urgency {
+    match priority {
+        ..=3 => Urgency::Low,
+        4..=7 => Urgency::Normal,
+        _ => Urgency::Critical,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Message;
+
+    #[test]
+    fn clamps_the_priority() {
+        let high: Message = serde_json::from_str(r#"{"message": "m", "priority": 42}"#).unwrap();
+        assert_eq!(high.priority, 10);
+
+        let low: Message = serde_json::from_str(r#"{"message": "m", "priority": -3}"#).unwrap();
+        assert_eq!(low.priority, 0);
+
+        let default: Message = serde_json::from_str(r#"{"message": "m"}"#).unwrap();
+        assert_eq!(default.priority, 5);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{bail, Result};
+use log::info;
+use axum::{
+    extract::{Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tokio::{net::TcpListener, sync::Mutex};
+
+use crate::{
+    client::{Client, NoHost},
+    daemon::{node::Node, protocol},
+    notification::{history, source::NotificationInput, Notification, NotificationId},
+    peers,
+    AppMode, CONFIG, DEVICE_ID,
+};
+
+mod gotify;
+mod ntfy;
+mod stream;
+
+#[derive(Clone)]
+pub struct ApiState {
+    node: Arc<Node<protocol::Response>>,
+    client: Arc<Client>,
+    host: Arc<Mutex<AppMode<SocketAddr>>>,
+}
+
+impl ApiState {
+    pub fn new(
+        node: Arc<Node<protocol::Response>>,
+        host: Arc<Mutex<AppMode<SocketAddr>>>,
+    ) -> Self {
+        Self {
+            client: Arc::new(Client::new(node.clone(), host.clone())),
+            node,
+            host,
+        }
+    }
+}
+
+pub async fn serve(state: ApiState) -> Result<()> {
+    if CONFIG.http.token.is_none() && !CONFIG.http.listen.ip().is_loopback() {
+        bail!("refusing to listen on {} without a token, set http.token", CONFIG.http.listen);
+    }
+
+    let mut app = Router::new()
+        .route("/notify", post(notify))
+        .route("/history", get(history))
+        .route("/peers", get(peers))
+        .route("/status", get(status))
+        .route("/events", get(stream::events))
+        .route("/metrics", get(metrics));
+
+    if CONFIG.http.ntfy {
+        app = app.merge(ntfy::routes());
+    }
+
+    if CONFIG.http.gotify {
+        app = app.merge(gotify::routes());
+    }
+
+    let app = app.layer(middleware::from_fn(auth)).with_state(state);
+
+    let listener = TcpListener::bind(CONFIG.http.listen).await?;
+    info!("http api listening on {}", CONFIG.http.listen);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// the token is taken from `Authorization: Bearer`, the `X-Gotify-Key` header or the `token` query parameter,
+/// the ways ntfy and Gotify clients send theirs
+async fn auth(request: Request, next: Next) -> Response {
+    if let Some(token) = &CONFIG.http.token
+        && !has_token(&request, token)
+    {
+        return ApiError(StatusCode::UNAUTHORIZED, "invalid token".to_string()).into_response();
+    }
+
+    next.run(request).await
+}
+
+fn has_token(request: &Request, token: &str) -> bool {
+    let headers = request.headers();
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let gotify_key = headers.get("X-Gotify-Key").and_then(|value| value.to_str().ok());
+    let query = Query::<TokenQuery>::try_from_uri(request.uri()).ok().and_then(|Query(query)| query.token);
+
+    [bearer, gotify_key, query.as_deref()]
+        .into_iter()
+        .flatten()
+        .fold(false, |found, given| found | bool::from(given.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// percent-decoded, unlike the raw query string
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// failing to reach the host
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError(StatusCode::BAD_GATEWAY, format!("{:#}", e))
+    }
+}
+
+async fn deliver(state: &ApiState, notification: Notification) -> Result<NotificationId, ApiError> {
+    state.client.deliver(notification).await.map_err(|e| match e.downcast_ref::<NoHost>() {
+        Some(_) => ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        None => e.into(),
+    })
+}
+
+async fn notify(State(state): State<ApiState>, Json(input): Json<NotificationInput>) -> Result<impl IntoResponse, ApiError> {
+    let id = deliver(&state, input.into_notification("http")).await?;
+    Ok((StatusCode::ACCEPTED, Json(json!({ "id": id.to_string() }))))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+async fn history(Query(query): Query<HistoryQuery>) -> impl IntoResponse {
+    Json(history::recent(query.limit.unwrap_or(50)).await)
+}
+
+async fn peers() -> impl IntoResponse {
+    Json(peers::list().await)
+}
+
+async fn metrics(State(state): State<ApiState>) -> impl IntoResponse {
+    let is_host = state.host.lock().await.is_host();
+    let peers = peers::len().await;
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::metrics::render(is_host, peers),
+    )
+}
+
+#[derive(Serialize)]
+struct Status {
+    device_id: String,
+    version: &'static str,
+    role: &'static str,
+    addr: SocketAddr,
+    host: Option<SocketAddr>,
+    peers: usize,
+}
+
+async fn status(State(state): State<ApiState>) -> impl IntoResponse {
+    let mode = state.host.lock().await.clone();
+    Json(Status {
+        device_id: DEVICE_ID.clone(),
+        version: env!("CARGO_PKG_VERSION"),
+        role: if mode.is_host() { "host" } else { "client" },
+        addr: state.node.addr(),
+        host: mode.get_host().copied(),
+        peers: peers::len().await,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request};
+
+    use super::has_token;
+
+    fn request(uri: &str) -> Request {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn reads_the_token_from_headers_and_query() {
+        let bearer = Request::builder().uri("/notify").header("Authorization", "Bearer s3cret").body(Body::empty());
+        assert!(has_token(&bearer.unwrap(), "s3cret"));
+
+        let gotify = Request::builder().uri("/message").header("X-Gotify-Key", "s3cret").body(Body::empty());
+        assert!(has_token(&gotify.unwrap(), "s3cret"));
+
+        assert!(has_token(&request("/events?app=mail&token=s3cret"), "s3cret"));
+        assert!(!has_token(&request("/events?token=other"), "s3cret"));
+        assert!(!has_token(&request("/events"), "s3cret"));
+    }
+
+    #[test]
+    fn decodes_the_query_token() {
+        assert!(has_token(&request("/events?token=a%2Bb%26c%3D"), "a+b&c="));
+        assert!(!has_token(&request("/events?token=a%2Bb%26c%3D"), "a%2Bb%26c%3D"));
+    }
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::notification::{Notification, Urgency};
+
+use super::{deliver, ApiError, ApiState};
+
+/// the publish API of ntfy under `/ntfy`, the server URL given to ntfy clients, so topics never shadow
+/// the routes of gon: `PUT/POST /ntfy/<topic>` with the message as body and `POST /ntfy` with a JSON message
+pub fn routes() -> Router<ApiState> {
+    Router::new()
+        .route("/ntfy", post(publish_json))
+        .route("/ntfy/{topic}", post(publish).put(publish))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PublishQuery {
+    #[serde(alias = "t")]
+    title: Option<String>,
+    #[serde(alias = "m")]
+    message: Option<String>,
+    #[serde(alias = "p", alias = "prio")]
+    priority: Option<String>,
+    #[serde(alias = "ta", alias = "tag")]
+    tags: Option<String>,
+}
+
+/// first of the headers ntfy accepts for a field, then the query parameter
+fn field(headers: &HeaderMap, names: &[&str], query: Option<String>) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| headers.get(*name).and_then(|value| value.to_str().ok()))
+        .map(str::to_string)
+        .or(query)
+}
+
+async fn publish(
+    State(state): State<ApiState>,
+    Path(topic): Path<String>,
+    Query(query): Query<PublishQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<impl IntoResponse, ApiError> {
+    let title = field(&headers, &["X-Title", "Title", "ti", "t"], query.title);
+    let priority = field(&headers, &["X-Priority", "Priority", "prio", "p"], query.priority);
+    let tags = field(&headers, &["X-Tags", "Tags", "tag", "ta"], query.tags);
+
+    let message = match body.trim() {
+        "" => field(&headers, &["X-Message", "Message", "m"], query.message).unwrap_or_else(|| "triggered".to_string()),
+        body => body.to_string(),
+    };
+
+    let tags = tags.map(|tags| tags.split(',').map(|tag| tag.trim().to_string()).collect()).unwrap_or_default();
+    let priority = priority.as_deref().map(parse_priority).unwrap_or(3);
+    publish_message(&state, topic, title, message, priority, tags).await
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonMessage {
+    topic: String,
+    title: Option<String>,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    priority: Option<u8>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// ntfy clients do not always set a JSON content type, so the body is parsed whatever it is
+async fn publish_json(State(state): State<ApiState>, body: String) -> Result<impl IntoResponse, ApiError> {
+    let msg: JsonMessage = serde_json::from_str(&body).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let message = if msg.message.is_empty() { "triggered".to_string() } else { msg.message };
+    publish_message(&state, msg.topic, msg.title, message, msg.priority.unwrap_or(3), msg.tags).await
+}
+
+async fn publish_message(
+    state: &ApiState,
+    topic: String,
+    title: Option<String>,
+    message: String,
+    priority: u8,
+    tags: Vec<String>,
+) -> Result<impl IntoResponse + use<>, ApiError> {
+    // ntfy shows the topic when there is no title
+    let mut notification = Notification::new(topic.clone(), title.clone().unwrap_or_else(|| topic.clone()), message.clone());
+    notification.urgency = urgency(priority);
+    if !tags.is_empty() {
+        notification.category = Some(tags.join(","));
+    }
+
+    let id = deliver(state, notification).await?;
+    Ok(Json(json!({
+        "id": id.to_string(),
+        "event": "message",
+        "topic": topic,
+        "title": title,
+        "message": message,
+        "priority": priority,
+        "tags": tags,
+    })))
+}
+
+/// ntfy priorities go from 1 (min) to 5 (max), 3 being the default
+fn parse_priority(priority: &str) -> u8 {
+    match priority.trim().to_lowercase().as_str() {
+        "min" => 1,
+        "low" => 2,
+        "high" => 4,
+        "max" | "urgent" => 5,
+        other => other.parse().unwrap_or(3),
+    }
+}
+
+fn urgency(priority: u8) -> Urgency {
+    match priority {
+        ..=2 => Urgency::Low,
+        3 => Urgency::Normal,
+        _ => Urgency::Critical,
+    }
+}
@@ -0,0 +1,62 @@
+use std::future::ready;
+
+use axum::{
+    extract::Query,
+    response::{
+        sse::{Event, KeepAlive},
+        IntoResponse, Sse,
+    },
+};
+use futures::{stream, StreamExt};
+use log::warn;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{self, NodeEvent};
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    /// only the notifications of this app, by name or id
+    app: Option<String>,
+    /// only the notifications captured by this device
+    device: Option<String>,
+    /// comma separated event names, all when unset
+    events: Option<String>,
+}
+
+impl StreamQuery {
+    fn matches(&self, event: &NodeEvent) -> bool {
+        if let Some(events) = &self.events
+            && !events.split(',').any(|name| name.trim() == event.name())
+        {
+            return false;
+        }
+
+        // app and device only narrow the notifications, node state changes always go through
+        let NodeEvent::Notification(record) = event else {
+            return true;
+        };
+
+        self.app.as_ref().is_none_or(|app| *app == record.app_name || *app == record.app_id)
+            && self.device.as_ref().is_none_or(|device| *device == record.device)
+    }
+}
+
+/// every node event as it happens, one JSON Server-Sent Event named after the event
+pub async fn events(Query(query): Query<StreamQuery>) -> impl IntoResponse {
+    let events = stream::unfold(events::subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(RecvError::Lagged(n)) => warn!("event stream lagged, {} events dropped", n),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = events
+        .filter(move |event| ready(query.matches(event)))
+        .map(|event| Event::default().event(event.name()).json_data(&event));
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,211 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+
+use crate::{config::LogConfig, DIRS};
+
+/// overrides the filter of the config, e.g. `GON_LOG=debug` or `GON_LOG=info,gon::daemon=trace`
+const FILTER_ENV: &str = "GON_LOG";
+
+/// levels by module path, like `warn,gon=info,gon::daemon=debug`
+struct Filter {
+    default: LevelFilter,
+    /// the longest matching prefix wins
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(spec: &str) -> Result<Self> {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => filter.modules.push((module.trim().to_string(), parse_level(level)?)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+
+        filter.modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(filter)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    level.trim().parse().map_err(|_| anyhow!("invalid log level {}", level))
+}
+
+struct Logger {
+    filter: Filter,
+    json: bool,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let time = timestamp();
+        let line = if self.json {
+            json!({
+                "time": time,
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string()
+        } else {
+            format!("{} {:<5} {}: {}", time, record.level(), record.target(), record.args())
+        };
+
+        if record.level() <= Level::Warn {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+
+        if let Some(file) = &self.file
+            && let Ok(mut file) = file.lock()
+        {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file
+            && let Ok(mut file) = file.lock()
+        {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// install the logger, the `log` macros print nothing before
+pub fn init(config: &LogConfig) -> Result<()> {
+    let spec = std::env::var(FILTER_ENV).unwrap_or_else(|_| config.filter.clone());
+    let filter = Filter::parse(&spec)?;
+
+    let file = if config.file {
+        let dir = DIRS.data_dir();
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(dir.join("gon.log"))?;
+        Some(Mutex::new(file))
+    } else {
+        None
+    };
+
+    log::set_max_level(filter.max());
+    log::set_boxed_logger(Box::new(Logger {
+        filter,
+        json: config.json,
+        file,
+    }))?;
+
+    Ok(())
+}
+
+fn timestamp() -> String {
+    format_time(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// RFC 3339 in UTC with milliseconds, of a time since the epoch
+fn format_time(now: Duration) -> String {
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+/// days since 1970-01-01 to a date of the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use log::LevelFilter;
+
+    use super::{civil_from_days, format_time, Filter};
+
+    #[test]
+    fn parses_levels_by_module() {
+        let filter = Filter::parse("warn, gon=info,gon::daemon=debug").unwrap();
+        assert_eq!(filter.level("reqwest"), LevelFilter::Warn);
+        assert_eq!(filter.level("gon"), LevelFilter::Info);
+        assert_eq!(filter.level("gon::client"), LevelFilter::Info);
+        assert_eq!(filter.level("gon::daemon::node"), LevelFilter::Debug);
+        assert_eq!(filter.level("gonzo"), LevelFilter::Warn);
+        assert_eq!(filter.max(), LevelFilter::Debug);
+
+        assert_eq!(Filter::parse("").unwrap().level("gon"), LevelFilter::Info);
+        assert_eq!(Filter::parse("TRACE").unwrap().level("gon"), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn rejects_unknown_levels() {
+        assert!(Filter::parse("verbose").is_err());
+        assert!(Filter::parse("info,gon=loud").is_err());
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+
+        assert_eq!(format_time(Duration::ZERO), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_time(Duration::from_millis(1_709_251_199_999)), "2024-02-29T23:59:59.999Z");
+    }
+}
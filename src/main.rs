@@ -3,29 +3,60 @@
     windows_subsystem = "windows"
 )]
 
-use std::{collections::HashSet, net::SocketAddr, sync::{Arc, LazyLock}, time::Duration};
+use std::{net::SocketAddr, sync::{Arc, LazyLock, OnceLock}, time::Duration};
 
-use crate::notification::SystemNotificationListener;
-use anyhow::Result;
+use crate::notification::{NotificationEvent, SystemNotificationListener};
+use config::Config;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use log::{debug, error, info, warn};
 use client::Client;
 use daemon::{
     node::Node,
-    protocol::Response,
-    service::{AppService, AppServiceEvent},
+    protocol::{Method, Payload, Response, PROTOCOL_VERSION},
+    service::{AppService, AppServiceEvent, NodeProperties},
 };
 use directories::ProjectDirs;
-use tokio::{select, sync::Mutex};
-use tray::{set_icon, TrayEvent, TrayIcon};
+use events::NodeEvent;
+use tokio::{
+    select,
+    sync::{mpsc::unbounded_channel, Mutex},
+};
+use tray::{set_icon, set_peers, TrayEvent, TrayIcon};
 
 mod client;
+mod config;
 mod daemon;
+mod events;
+mod http;
+mod logging;
+mod metrics;
+mod mqtt;
 mod notification;
+mod peers;
+mod status;
 mod tray;
 
 pub static DIRS: LazyLock<ProjectDirs> = LazyLock::new(|| {
     ProjectDirs::from("", "", "gon").unwrap()
 });
 
+/// set in `main` like the config, a device id which cannot be stored is reported as an error
+static LOADED_DEVICE_ID: OnceLock<String> = OnceLock::new();
+
+/// an id only kept in memory is used when nothing was loaded, as in tests
+pub static DEVICE_ID: LazyLock<String> = LazyLock::new(|| {
+    LOADED_DEVICE_ID.get_or_init(daemon::misc::random_device_id).clone()
+});
+
+/// set first thing in `main`, so a broken config.toml is reported as an error
+static LOADED_CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// the defaults are only used when nothing was loaded, as in tests
+pub static CONFIG: LazyLock<&'static Config> = LazyLock::new(|| {
+    LOADED_CONFIG.get_or_init(Config::default)
+});
+
 #[derive(Clone, Debug)]
 pub enum AppMode<T> {
     Host,
@@ -54,65 +85,171 @@ impl<T> AppMode<T> {
     }
 }
 
+#[derive(Parser)]
+#[command(version, about = "Gate of Notification")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// print the state of the running daemon
+    Status {
+        /// print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// check this device can send and receive notifications
+    Doctor,
+    /// list the nodes known by the running daemon
+    Peers {
+        /// print the peers as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let _ = LOADED_CONFIG.set(Config::load().context("invalid config.toml")?);
+    logging::init(&CONFIG.log)?;
+    let _ = LOADED_DEVICE_ID.set(daemon::misc::load_device_id().context("failed to load the device id")?);
+
+    match cli.command {
+        Some(Command::Status { json }) => return status::status(json).await,
+        Some(Command::Doctor) => return status::doctor().await,
+        Some(Command::Peers { json }) => return status::peers(json).await,
+        None => {},
+    }
+
+    notification::sink::start();
     let (mut tray, mut tray_rx) = tray::init_tray();
 
     let mut listener = SystemNotificationListener::default();
-    listener.listen();
+    let mut event_rx = listener.listen();
 
     let mut node = Node::new().await?;
     let mut messaeg_rx = node.listen().await?;
     let node = Arc::new(node);
-    let mut service = AppService::new(node.addr)?;
-
+    if let Err(e) = status::write_addr(node.addr()) {
+        warn!("failed to write node address for the commands: {:?}", e);
+    }
     let host: Arc<Mutex<AppMode<SocketAddr>>> = Arc::new(Mutex::new(AppMode::Client(None)));
+    let properties = NodeProperties::local(node.key_fingerprint(), &*host.lock().await);
+    let mut service = AppService::new(node.addr(), properties)?;
+
     let client = Client::new(node.clone(), host.clone());
 
     let mut check_interval = tokio::time::interval(Duration::from_secs(30)); 
+    // a single check of the peers at a time, a slow one skips the next ticks
+    let (checked_tx, mut checked_rx) = unbounded_channel();
+    let mut checking = false;
+    let mut node_events = events::subscribe();
 
-    let addr_book: Arc<Mutex<HashSet<SocketAddr>>> = Arc::new(Mutex::new(HashSet::new()));
+    if CONFIG.http.enabled {
+        let state = http::ApiState::new(node.clone(), host.clone());
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(state).await {
+                error!("http api error: {:?}", e);
+            }
+        });
+    }
+
+    if CONFIG.mqtt.enabled {
+        let client = Arc::new(Client::new(node.clone(), host.clone()));
+        tokio::spawn(async move {
+            if let Err(e) = mqtt::run(client).await {
+                error!("mqtt bridge error: {:?}", e);
+            }
+        });
+    }
 
     loop {
         select! {
-            // try to get host addr in lan per 30 seconds if node is client and not found host
+            // check the known peers and try to get host addr in lan per 30 seconds if node is client and not found host
             _ = check_interval.tick() => {
-                let host = host.lock().await;
-                if !host.is_client_and_not_found_host() || host.is_host() {
+                match node.refresh_addr().await {
+                    Ok(Some(addr)) => {
+                        info!("address changed to {}", addr);
+                        if let Err(e) = service.set_addr(addr).await {
+                            warn!("failed to register services on {}: {:?}", addr, e);
+                        }
+
+                        if let Err(e) = status::write_addr(addr) {
+                            warn!("failed to write node address for the commands: {:?}", e);
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(e) => warn!("failed to listen on the new address of the device: {:?}", e),
+                }
+
+                if !checking {
+                    checking = true;
+                    let client = Client::new(node.clone(), host.clone());
+                    let checked_tx = checked_tx.clone();
+                    tokio::spawn(async move {
+                        peers::check(client).await;
+                        let _ = checked_tx.send(());
+                    });
+                }
+
+                if !host.lock().await.is_client_and_not_found_host() {
                     continue;
                 }
 
-                println!("try to get host addr in lan");
-                'a: for addr in addr_book.lock().await.iter() {
-                    if let Ok(mut stream) = client.connect(*addr).await {
-                        if let Ok(()) = stream.get_addr().await {
-                            break 'a;
-                        }
+                debug!("try to get host addr in lan");
+                for addr in peers::addrs().await {
+                    if let Ok(mut stream) = client.connect(addr).await
+                        && let Ok(()) = stream.get_addr().await
+                    {
+                        break;
                     }
                 }
             }
+            Some(()) = checked_rx.recv() => {
+                checking = false;
+                set_peers(&mut tray, &peers::list().await);
+            }
+            Ok(event) = node_events.recv() => {
+                match event {
+                    NodeEvent::PeerDiscovered { .. } | NodeEvent::PeerLost { .. } => {
+                        set_peers(&mut tray, &peers::list().await);
+                    },
+                    NodeEvent::HostChanged { role, .. } => {
+                        if let Err(e) = service.set_role(role) {
+                            warn!("failed to announce role {}: {:?}", role, e);
+                        }
+                    },
+                    NodeEvent::Notification(_) => {},
+                }
+            }
             Some(event) = tray_rx.recv() => {
                 match event {
                     TrayEvent::BecomeHost => {
-                        println!("become host");
+                        info!("become host");
                         let mut host = host.lock().await;
                         let _host = host.clone();
                         let origin_host = _host.get_host();
 
                         *host = AppMode::Host;
+                        events::host_changed(&host);
                         set_icon(&mut tray, TrayIcon::Host);
 
                         // if host exist, send im_host to host
                         if let Some(host) = origin_host {
-                            println!("tell {} i'm host", host);
+                            info!("tell {} i'm host", host);
                             if let Ok(mut stream) = client.connect(*host).await {
                                 let _ = stream.im_host().await;
                             }
                         }
                     }
                     TrayEvent::BecomeClient => {
-                        println!("become client");
-                        *host.lock().await = AppMode::Client(None);
+                        info!("become client");
+                        let mut host = host.lock().await;
+                        *host = AppMode::Client(None);
+                        events::host_changed(&host);
                         set_icon(&mut tray, TrayIcon::Default);
                     }
                     TrayEvent::Quit => {
@@ -122,24 +259,51 @@ async fn main() -> Result<()> {
             }
             Ok(event) = service.next() => {
                 match event {
-                    AppServiceEvent::NodeDiscoverd(socket_addr) => {
-                        if host.lock().await.is_host() {
+                    AppServiceEvent::NodeDiscoverd(socket_addr, Some(properties)) => {
+                        metrics::DISCOVERY_EVENTS.inc();
+                        info!("discoverd {} ({}, {})", socket_addr, properties.name, properties.role);
+                        if properties.protocol != PROTOCOL_VERSION || properties.fingerprint != node.key_fingerprint() {
+                            warn!(
+                                "skip {}: protocol {} and key {} do not match {} and {}",
+                                socket_addr, properties.protocol, properties.fingerprint, PROTOCOL_VERSION, node.key_fingerprint()
+                            );
                             continue;
                         }
 
-                        println!("discoverd {}", socket_addr);
-                        let Ok(mut stream) = client.connect(socket_addr).await else {
+                        peers::advertised(socket_addr, &properties).await;
+
+                        // anyone can advertise a record, a role change is only believed once the node
+                        // confirms it in the encrypted handshake
+                        let AppMode::Client(current) = *host.lock().await else {
                             continue;
                         };
 
-                        if stream.ping().await {
-                            println!("ping pong sucess");
-                            let mut addr_book = addr_book.lock().await;
-                            addr_book.insert(socket_addr);
+                        if properties.is_host() != (current == Some(socket_addr)) {
+                            let client = Client::new(node.clone(), host.clone());
+                            tokio::spawn(confirm_role(client, host.clone(), socket_addr, Some(properties.device_id)));
+                        }
+                    },
+                    // nodes which do not advertise their properties are asked
+                    AppServiceEvent::NodeDiscoverd(socket_addr, None) => {
+                        metrics::DISCOVERY_EVENTS.inc();
+                        if socket_addr == node.addr() {
+                            continue;
+                        }
+
+                        // the handshake tells the role as well
+                        info!("discoverd {}", socket_addr);
+                        let client = Client::new(node.clone(), host.clone());
+                        tokio::spawn(confirm_role(client, host.clone(), socket_addr, None));
+                    },
+                    AppServiceEvent::NodeLost(socket_addr) => {
+                        info!("lost {}", socket_addr);
+                        peers::remove(socket_addr).await;
 
-                            if let Ok(mut stream) = client.connect(socket_addr).await {
-                                let _ = stream.get_addr().await;
-                            }
+                        let mut host = host.lock().await;
+                        if host.get_host() == Some(&socket_addr) {
+                            info!("host {} left", socket_addr);
+                            *host = AppMode::Client(None);
+                            events::host_changed(&host);
                         }
                     },
                     AppServiceEvent::None => continue,
@@ -151,33 +315,92 @@ async fn main() -> Result<()> {
                     continue;
                 }
 
+                metrics::CAPTURED.inc();
                 let host = host.lock().await;
-                if host.is_host() || host.is_client_and_not_found_host() {
+                if host.is_host() {
                     continue;
                 }
 
                 let AppMode::Client(Some(host)) = *host else {
+                    metrics::DROPPED_NO_HOST.inc();
                     continue;
                 };
 
-                println!("send notification to {}", host);
-                if let Ok(mut stream) = client.connect(host).await {
-                    let _ = stream.send_notification(Arc::into_inner(notif).unwrap()).await;
+                info!("send notification to {}", host);
+                let sent = match client.connect(host).await {
+                    Ok(mut stream) => stream.send_notification(Arc::into_inner(notif).unwrap()).await,
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = sent {
+                    metrics::DROPPED_FORWARD_FAILED.inc();
+                    warn!("failed to send notification to {}: {:?}", host, e);
+                }
+            }
+            Some(event) = event_rx.recv() => {
+                match event {
+                    NotificationEvent::ActionInvoked { origin, notification_id, action_key } => {
+                        info!("send action {} of notification {} to {}", action_key, notification_id, origin);
+                        if let Ok(mut stream) = client.connect(origin).await {
+                            let _ = stream.invoke_action(notification_id, action_key).await;
+                        }
+                    }
+                    NotificationEvent::Replied { origin, notification_id, text } => {
+                        info!("send reply of notification {} to {}", notification_id, origin);
+                        if let Ok(mut stream) = client.connect(origin).await {
+                            let _ = stream.reply(notification_id, text).await;
+                        }
+                    }
+                    NotificationEvent::Closed { origin, notification_id } => {
+                        // closed on the source device goes to the host, closed on the host goes back to the source
+                        let target = match origin {
+                            Some(origin) => origin,
+                            None => match host.lock().await.get_host() {
+                                Some(host) => *host,
+                                None => continue,
+                            },
+                        };
+
+                        info!("send closing of notification {} to {}", notification_id, target);
+                        if let Ok(mut stream) = client.connect(target).await {
+                            let _ = stream.notification_closed(notification_id).await;
+                        }
+                    }
                 }
             }
             Some((mut stream, msg)) = messaeg_rx.recv() => {
-                println!("Received new Message {:?}", msg);
+                debug!("Received new Message {:?}", msg);
                 if msg.is_done() {
                     continue;
                 }
 
+                // answered whatever the role, the ping to the host must not hold the loop
+                if msg.method == Method::Status {
+                    let mode = host.lock().await.clone();
+                    let mdns_service = service.fullname().to_string();
+                    let mdns_running = service.is_running();
+                    let node = node.clone();
+                    tokio::spawn(async move {
+                        let report = status::report(node.clone(), mode, mdns_service, mdns_running.await).await;
+                        let res = match serde_json::to_string(&report) {
+                            Ok(json) => Response::success(Payload::Text(json)),
+                            Err(_) => Response::failed(),
+                        };
+                        let _ = node.reply(&mut stream, res).await;
+                    });
+                    continue;
+                }
+
                 // if not host
-                let res = if let AppMode::Client(Some(host)) = *host.lock().await {
-                    println!("i'm not host, host changed to {}", host);
+                let res = if !msg.is_callback()
+                    && msg.method != Method::Hello
+                    && let AppMode::Client(Some(host)) = *host.lock().await
+                {
+                    info!("i'm not host, host changed to {}", host);
                     Response::host_changed(host)
                 } else {
                     let handler = client.handle();
-                    handler.handle(msg).await
+                    handler.handle(msg, stream.peer_addr().ok()).await
                 };
 
                 let _ = node.reply(&mut stream, res).await;
@@ -185,5 +408,58 @@ async fn main() -> Result<()> {
         }
     }
 
+    if let Err(e) = service.unregister().await {
+        warn!("failed to unregister services: {:?}", e);
+    }
+
     Ok(())
 }
+
+/// how long a node gets to confirm its role
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// handshake with the node, then take it as host when it is one, or leave it when it no longer is,
+/// the handshake is only answered by nodes sharing the key. `device_id` is the one it advertised
+async fn confirm_role(
+    client: Client,
+    host: Arc<Mutex<AppMode<SocketAddr>>>,
+    addr: SocketAddr,
+    device_id: Option<String>,
+) {
+    let hello = async { client.connect(addr).await?.hello().await };
+    let info = match tokio::time::timeout(CONFIRM_TIMEOUT, hello).await {
+        Ok(Ok(info)) => info,
+        Ok(Err(e)) => {
+            warn!("{} did not confirm its role: {:?}", addr, e);
+            return;
+        },
+        Err(_) => {
+            warn!("{} did not confirm its role in {:?}", addr, CONFIRM_TIMEOUT);
+            return;
+        },
+    };
+
+    // an answer recorded from another node does not count
+    if info.addr != addr || device_id.is_some_and(|id| id != info.device_id) {
+        warn!("{} answered as {} ({}), ignore its record", addr, info.addr, info.device_id);
+        return;
+    }
+
+    let is_host = info.role == "host";
+    peers::seen(addr, Some(info)).await;
+
+    let mut host = host.lock().await;
+    let AppMode::Client(current) = *host else {
+        return;
+    };
+
+    if is_host && current != Some(addr) {
+        info!("host found on mdns: {}", addr);
+        *host = AppMode::Client(Some(addr));
+        events::host_changed(&host);
+    } else if !is_host && current == Some(addr) {
+        info!("host {} became client", addr);
+        *host = AppMode::Client(None);
+        events::host_changed(&host);
+    }
+}
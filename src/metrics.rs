@@ -0,0 +1,132 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// upper bounds of the frame size buckets, in bytes
+const FRAME_BUCKETS: [u64; 8] = [64, 256, 1024, 4096, 16384, 65536, 262144, 1048576];
+
+pub struct Histogram {
+    /// not cumulative, summed up when rendered
+    buckets: [AtomicU64; FRAME_BUCKETS.len()],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; FRAME_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        if let Some(i) = FRAME_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+/// notifications captured by the sources of this node
+pub static CAPTURED: Counter = Counter::new();
+/// notifications sent to the host
+pub static FORWARDED: Counter = Counter::new();
+/// notifications received by the host, duplicates excluded
+pub static RECEIVED: Counter = Counter::new();
+/// notifications shown by the desktop sink
+pub static DISPLAYED: Counter = Counter::new();
+pub static DROPPED_DUPLICATE: Counter = Counter::new();
+pub static DROPPED_NO_HOST: Counter = Counter::new();
+pub static DROPPED_FORWARD_FAILED: Counter = Counter::new();
+pub static SINK_ERRORS: Counter = Counter::new();
+/// failures to connect to or to talk with another node
+pub static CONNECTION_ERRORS: Counter = Counter::new();
+pub static DECRYPT_FAILURES: Counter = Counter::new();
+pub static DISCOVERY_EVENTS: Counter = Counter::new();
+pub static FRAMES_SENT: Histogram = Histogram::new();
+pub static FRAMES_RECEIVED: Histogram = Histogram::new();
+
+/// every metric in the Prometheus text format
+pub fn render(is_host: bool, peers: usize) -> String {
+    let mut out = String::new();
+
+    counter(&mut out, "gon_notifications_captured_total", "Notifications captured on this node.", &[("", &CAPTURED)]);
+    counter(&mut out, "gon_notifications_forwarded_total", "Notifications sent to the host.", &[("", &FORWARDED)]);
+    counter(&mut out, "gon_notifications_received_total", "Notifications received by the host.", &[("", &RECEIVED)]);
+    counter(&mut out, "gon_notifications_displayed_total", "Notifications shown on the desktop.", &[("", &DISPLAYED)]);
+    counter(
+        &mut out,
+        "gon_notifications_dropped_total",
+        "Notifications dropped, by reason.",
+        &[
+            ("reason=\"duplicate\"", &DROPPED_DUPLICATE),
+            ("reason=\"no_host\"", &DROPPED_NO_HOST),
+            ("reason=\"forward_failed\"", &DROPPED_FORWARD_FAILED),
+        ],
+    );
+    counter(&mut out, "gon_sink_errors_total", "Notifications a sink failed to output.", &[("", &SINK_ERRORS)]);
+    counter(&mut out, "gon_connection_errors_total", "Failed connections between nodes.", &[("", &CONNECTION_ERRORS)]);
+    counter(&mut out, "gon_decrypt_failures_total", "Frames which could not be decrypted.", &[("", &DECRYPT_FAILURES)]);
+    counter(&mut out, "gon_discovery_events_total", "Nodes discovered over mDNS.", &[("", &DISCOVERY_EVENTS)]);
+
+    let _ = writeln!(out, "# HELP gon_frame_bytes Size of the encrypted frames between nodes.");
+    let _ = writeln!(out, "# TYPE gon_frame_bytes histogram");
+    histogram(&mut out, "gon_frame_bytes", "direction=\"sent\"", &FRAMES_SENT);
+    histogram(&mut out, "gon_frame_bytes", "direction=\"received\"", &FRAMES_RECEIVED);
+
+    let _ = writeln!(out, "# HELP gon_role Current role of this node.");
+    let _ = writeln!(out, "# TYPE gon_role gauge");
+    let _ = writeln!(out, "gon_role{{role=\"host\"}} {}", u8::from(is_host));
+    let _ = writeln!(out, "gon_role{{role=\"client\"}} {}", u8::from(!is_host));
+
+    let _ = writeln!(out, "# HELP gon_peers Known nodes.");
+    let _ = writeln!(out, "# TYPE gon_peers gauge");
+    let _ = writeln!(out, "gon_peers {}", peers);
+
+    out
+}
+
+fn counter(out: &mut String, name: &str, help: &str, series: &[(&str, &Counter)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, counter) in series {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, counter.get());
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, counter.get());
+        }
+    }
+}
+
+fn histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, bucket) in FRAME_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+    }
+
+    let count = histogram.count.load(Ordering::Relaxed);
+    let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, count);
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum.load(Ordering::Relaxed));
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+}
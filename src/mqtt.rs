@@ -0,0 +1,235 @@
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+use crate::{
+    client::Client,
+    config::MqttConfig,
+    notification::{sink::NotificationRecord, source::NotificationInput},
+    CONFIG, DEVICE_ID,
+};
+
+/// connection of the bridge, used by the `mqtt` sink
+static MQTT: OnceLock<AsyncClient> = OnceLock::new();
+
+/// longest wait between two reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// keep a connection to the broker, reconnecting with a backoff, and inject the notifications
+/// published on the command topic
+pub async fn run(client: Arc<Client>) -> Result<()> {
+    bridge(&CONFIG.mqtt, move |input| {
+        tokio::spawn(inject(client.clone(), input));
+    })
+    .await
+}
+
+/// the connection loop of `run`, `on_command` gets the notifications published on the command topic
+async fn bridge(config: &MqttConfig, on_command: impl Fn(NotificationInput)) -> Result<()> {
+    let qos = qos(config.qos)?;
+    let client_id = config.client_id.clone().unwrap_or_else(|| format!("gon-{}", *DEVICE_ID));
+
+    let mut options = MqttOptions::new(client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+
+    let (mqtt, mut eventloop) = AsyncClient::new(options, 64);
+    let _ = MQTT.set(mqtt.clone());
+
+    let mut backoff = Duration::from_secs(1);
+    // set on every connection, a clean session loses the subscription
+    let mut subscribe = false;
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("connected to mqtt broker {}:{}", config.host, config.port);
+                backoff = Duration::from_secs(1);
+                subscribe = true;
+            },
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                match serde_json::from_slice::<NotificationInput>(&publish.payload) {
+                    Ok(input) => on_command(input),
+                    Err(e) => warn!("invalid notification from mqtt topic {}: {}", publish.topic, e),
+                }
+            },
+            Ok(_) => {},
+            Err(e) => {
+                // the next poll reconnects
+                warn!("mqtt connection error, reconnect in {:?}: {:?}", backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            },
+        }
+
+        // the request queue may be full, it is tried again after the next event
+        if subscribe {
+            match mqtt.try_subscribe(&config.command_topic, qos) {
+                Ok(()) => subscribe = false,
+                Err(e) => warn!("failed to subscribe to mqtt topic {}: {:?}", config.command_topic, e),
+            }
+        }
+    }
+}
+
+async fn inject(client: Arc<Client>, input: NotificationInput) {
+    match client.deliver(input.into_notification("mqtt")).await {
+        Ok(id) => info!("inject notification {} from mqtt", id),
+        Err(e) => error!("failed to inject notification from mqtt: {:?}", e),
+    }
+}
+
+/// publish the record to `<prefix>/<device>/<app>`
+pub async fn publish(record: &NotificationRecord) -> Result<()> {
+    let mqtt = MQTT.get().ok_or(anyhow!("mqtt bridge is not enabled"))?;
+    let config = &CONFIG.mqtt;
+    let topic = format!("{}/{}/{}", config.topic_prefix, topic_level(&record.device), topic_level(&record.app_name));
+
+    mqtt.publish(topic, qos(config.qos)?, config.retain, serde_json::to_vec(record)?).await?;
+    Ok(())
+}
+
+/// `/`, `+` and `#` have a meaning in topics
+fn topic_level(name: &str) -> String {
+    if name.is_empty() {
+        return "unknown".to_string();
+    }
+
+    name.replace(['/', '+', '#'], "_")
+}
+
+fn qos(qos: u8) -> Result<QoS> {
+    match qos {
+        0 => Ok(QoS::AtMostOnce),
+        1 => Ok(QoS::AtLeastOnce),
+        2 => Ok(QoS::ExactlyOnce),
+        _ => Err(anyhow!("invalid mqtt qos {}", qos)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, Packet, PubAck, Publish, QoS, SubAck, SubscribeReasonCode};
+    use serde_json::Value;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::unbounded_channel,
+        time::timeout,
+    };
+
+    use super::{bridge, publish};
+    use crate::{
+        config::MqttConfig,
+        notification::{sink::NotificationRecord, Notification, NotificationId},
+    };
+
+    const MAX_PACKET: usize = 64 * 1024;
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// the broker side of one connection, speaking just enough MQTT for the bridge
+    struct Broker {
+        stream: TcpStream,
+        buf: BytesMut,
+    }
+
+    impl Broker {
+        async fn accept(listener: &TcpListener) -> Self {
+            let (stream, _) = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+            let mut broker = Self { stream, buf: BytesMut::new() };
+
+            assert!(matches!(broker.read().await, Packet::Connect(..)));
+            broker.write(Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false))).await;
+            broker
+        }
+
+        async fn read(&mut self) -> Packet {
+            loop {
+                match Packet::read(&mut self.buf, MAX_PACKET) {
+                    Ok(packet) => return packet,
+                    Err(rumqttc::Error::InsufficientBytes(_)) => {
+                        let read = timeout(WAIT, self.stream.read_buf(&mut self.buf)).await.unwrap().unwrap();
+                        assert_ne!(read, 0, "the bridge closed the connection");
+                    },
+                    Err(e) => panic!("invalid packet: {:?}", e),
+                }
+            }
+        }
+
+        async fn write(&mut self, packet: Packet) {
+            let mut buf = BytesMut::new();
+            packet.write(&mut buf, MAX_PACKET).unwrap();
+            self.stream.write_all(&buf).await.unwrap();
+        }
+
+        /// the bridge subscribes to the command topic after connecting
+        async fn expect_subscribe(&mut self, topic: &str) {
+            let Packet::Subscribe(subscribe) = self.read().await else {
+                panic!("expected a subscription");
+            };
+
+            assert_eq!(subscribe.filters[0].path, topic);
+            let codes = vec![SubscribeReasonCode::Success(QoS::AtLeastOnce)];
+            self.write(Packet::SubAck(SubAck::new(subscribe.pkid, codes))).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn bridges_notifications_and_resubscribes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = MqttConfig {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            ..MqttConfig::default()
+        };
+
+        let (tx, mut rx) = unbounded_channel();
+        tokio::spawn(async move {
+            bridge(&config, move |input| {
+                let _ = tx.send(input);
+            })
+            .await
+        });
+
+        let mut broker = Broker::accept(&listener).await;
+        broker.expect_subscribe("gon/notify").await;
+
+        // a command is injected
+        let payload = r#"{"app_name": "ci", "title": "build", "message": "passed"}"#;
+        broker.write(Packet::Publish(Publish::new("gon/notify", QoS::AtMostOnce, payload))).await;
+        let input = timeout(WAIT, rx.recv()).await.unwrap().unwrap();
+        assert_eq!(input.app_name.as_deref(), Some("ci"));
+        assert_eq!(input.title, "build");
+        assert_eq!(input.message, "passed");
+
+        // a notification is published under the device and the application
+        let mut notification = Notification::new("Mail/Web".to_string(), "Hello".to_string(), String::new());
+        notification.id = NotificationId { device: "laptop".to_string(), seq: 1 };
+        let record = NotificationRecord::from(&notification);
+        publish(&record).await.unwrap();
+
+        let Packet::Publish(published) = broker.read().await else {
+            panic!("expected a publish");
+        };
+        assert_eq!(published.topic, "gon/laptop/Mail_Web");
+        let body: Value = serde_json::from_slice(&published.payload).unwrap();
+        assert_eq!(body["id"], "laptop-1");
+        broker.write(Packet::PubAck(PubAck::new(published.pkid))).await;
+
+        // the broker goes away, the bridge reconnects and subscribes again
+        drop(broker);
+        let mut broker = Broker::accept(&listener).await;
+        broker.expect_subscribe("gon/notify").await;
+    }
+}
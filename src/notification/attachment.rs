@@ -0,0 +1,105 @@
+use std::{fs, io::Cursor, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageReader, Limits, RgbImage, RgbaImage};
+
+use crate::{config::AttachmentConfig, DIRS};
+
+use super::icon::icon_hash;
+
+/// pixels of the freedesktop `image-data` hint
+pub struct RawImage {
+    pub width: i32,
+    pub height: i32,
+    pub rowstride: i32,
+    pub has_alpha: bool,
+    pub bits_per_sample: i32,
+    pub channels: i32,
+    pub data: Vec<u8>,
+}
+
+/// attached files larger than this are not read
+const MAX_FILE_BYTES: u64 = 32 * 1024 * 1024;
+/// memory the decoder may use for an attached file
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// load an attached image file, the result is a PNG within the configured bounds
+pub async fn from_file(path: PathBuf, config: &'static AttachmentConfig) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || {
+        let len = fs::metadata(&path)?.len();
+        if len > MAX_FILE_BYTES {
+            return Err(anyhow!("attached file of {} bytes exceeds the limit of {}", len, MAX_FILE_BYTES));
+        }
+
+        let mut limits = Limits::default();
+        limits.max_alloc = Some(MAX_DECODE_BYTES);
+
+        let mut reader = ImageReader::open(&path)?.with_guessed_format()?;
+        reader.limits(limits);
+        bounded(reader.decode()?, config)
+    })
+    .await?
+}
+
+/// convert the pixels of the `image-data` hint, the result is a PNG within the configured bounds
+pub async fn from_raw(raw: RawImage, config: &'static AttachmentConfig) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || convert(raw, config)).await?
+}
+
+fn convert(raw: RawImage, config: &AttachmentConfig) -> Result<Vec<u8>> {
+    if raw.bits_per_sample != 8 || raw.width <= 0 || raw.height <= 0 {
+        return Err(anyhow!("unsupported image data"));
+    }
+
+    let (width, height) = (raw.width as u32, raw.height as u32);
+    let channels = if raw.has_alpha { 4 } else { 3 };
+    if raw.channels != channels as i32 {
+        return Err(anyhow!("unsupported image data"));
+    }
+
+    // rows can be padded, keep only the pixels
+    let row_len = width as usize * channels;
+    let pixels: Vec<u8> = raw
+        .data
+        .chunks(raw.rowstride.max(1) as usize)
+        .take(height as usize)
+        .flat_map(|row| row.get(..row_len).unwrap_or(row))
+        .copied()
+        .collect();
+
+    let image = if raw.has_alpha {
+        RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+    }
+    .ok_or(anyhow!("truncated image data"))?;
+
+    bounded(image, config)
+}
+
+fn bounded(mut image: DynamicImage, config: &AttachmentConfig) -> Result<Vec<u8>> {
+    if image.width() > config.max_dimension || image.height() > config.max_dimension {
+        image = image.resize(config.max_dimension, config.max_dimension, FilterType::Triangle);
+    }
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    if png.len() > config.max_bytes {
+        return Err(anyhow!("attachment of {} bytes exceeds the limit of {}", png.len(), config.max_bytes));
+    }
+
+    Ok(png)
+}
+
+/// write the attachment into the cache dir, so it can be referenced by path, and return the path
+pub fn cache_attachment(data: &[u8]) -> Result<PathBuf> {
+    let dir = DIRS.cache_dir().join("attachments");
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(format!("{}.png", icon_hash(data)));
+    if !matches!(fs::exists(&path), Ok(true)) {
+        fs::write(&path, data)?;
+    }
+
+    Ok(path)
+}
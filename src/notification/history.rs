@@ -0,0 +1,28 @@
+use std::{collections::VecDeque, sync::LazyLock};
+
+use tokio::sync::Mutex;
+
+use crate::CONFIG;
+
+use super::sink::NotificationRecord;
+
+/// the notifications lately received by the host, oldest first
+static HISTORY: LazyLock<Mutex<VecDeque<NotificationRecord>>> = LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+pub async fn push(record: NotificationRecord) {
+    let capacity = CONFIG.http.history;
+    if capacity == 0 {
+        return;
+    }
+
+    let mut history = HISTORY.lock().await;
+    while history.len() >= capacity {
+        history.pop_front();
+    }
+    history.push_back(record);
+}
+
+/// up to `limit` notifications, newest first
+pub async fn recent(limit: usize) -> Vec<NotificationRecord> {
+    HISTORY.lock().await.iter().rev().take(limit).cloned().collect()
+}
@@ -0,0 +1,91 @@
+use std::{fs, io::Cursor, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, ImageFormat};
+use resvg::{tiny_skia, usvg};
+
+use crate::{daemon::misc::sha256_hex, DIRS};
+
+/// icons are sent as PNG fitting in a square of this size
+pub const ICON_SIZE: u32 = 64;
+
+/// decode an icon of any supported format (PNG, JPEG, ICO, BMP, GIF, SVG) and re-encode it
+/// as a PNG no larger than `ICON_SIZE`, unsupported formats such as XPM return an error
+pub async fn normalize_icon(data: Vec<u8>) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || normalize(&data)).await?
+}
+
+fn normalize(data: &[u8]) -> Result<Vec<u8>> {
+    if is_svg(data) {
+        return rasterize_svg(data);
+    }
+
+    let mut image = image::load_from_memory(data)?;
+    if image.width() > ICON_SIZE || image.height() > ICON_SIZE {
+        image = image.resize(ICON_SIZE, ICON_SIZE, FilterType::Lanczos3);
+    }
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+fn rasterize_svg(data: &[u8]) -> Result<Vec<u8>> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())?;
+    let size = tree.size();
+    let scale = ICON_SIZE as f32 / size.width().max(size.height());
+
+    let width = ((size.width() * scale).round() as u32).max(1);
+    let height = ((size.height() * scale).round() as u32).max(1);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or(anyhow!("invalid svg size"))?;
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    Ok(pixmap.encode_png()?)
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    data.starts_with(b"<?xml") || data.starts_with(b"<svg")
+}
+
+/// hex encoded sha256 of the icon bytes
+pub fn icon_hash(data: &[u8]) -> String {
+    sha256_hex(data)
+}
+
+/// write the icon into the cache dir under its hash, so it can be referenced by path, and return the path
+pub fn cache_icon(data: &[u8]) -> Result<PathBuf> {
+    let dir = icon_dir();
+    fs::create_dir_all(&dir)?;
+
+    // icons are normalized to PNG before leaving the source device
+    let path = dir.join(format!("{}.png", icon_hash(data)));
+    if !matches!(fs::exists(&path), Ok(true)) {
+        fs::write(&path, data)?;
+    }
+
+    Ok(path)
+}
+
+/// path of the cached icon with this hash, if any
+pub fn cached_icon(hash: &str) -> Option<PathBuf> {
+    if !is_valid_hash(hash) {
+        return None;
+    }
+
+    let path = icon_dir().join(format!("{}.png", hash));
+    matches!(fs::exists(&path), Ok(true)).then_some(path)
+}
+
+pub fn load_cached_icon(hash: &str) -> Result<Vec<u8>> {
+    let path = cached_icon(hash).ok_or(anyhow!("icon {} is not cached", hash))?;
+    Ok(fs::read(path)?)
+}
+
+fn icon_dir() -> PathBuf {
+    DIRS.cache_dir().join("icons")
+}
+
+/// hashes come from the network and end up in a path
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
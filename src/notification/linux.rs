@@ -1,72 +1,551 @@
 use anyhow::{anyhow, Result};
+use log::{error, info, warn};
 use freedesktop_icons::lookup;
+use notify_rust::Hint;
 use tokio_stream::StreamExt;
 use zbus::fdo::MonitoringProxy;
+use zbus::zvariant::OwnedValue;
 use zbus::MatchRule;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{Mutex, OnceCell};
 use tokio::sync::mpsc::UnboundedSender;
 use zbus::{connection::Connection, MessageStream};
 use zbus::message::{Message, Type};
 
-use super::Notification;
+use crate::config::CaptureMode;
+use crate::CONFIG;
 
-pub async fn notification_listener(tx: UnboundedSender<Arc<Notification>>) -> Result<()> {
+use super::attachment::{self, cache_attachment, RawImage};
+use super::icon::{cache_icon, cached_icon, normalize_icon, ICON_SIZE};
+use super::{Notification, NotificationAction, NotificationEvent, NotificationId, Urgency};
+
+mod server;
+
+use server::{serving, Serving};
+
+const PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// session bus connection used to talk to the notification server,
+/// the monitor connection can not send anything once it becomes a monitor
+static SESSION: OnceCell<Connection> = OnceCell::const_new();
+
+/// unique bus name of the application which sent each captured notification, by server id
+static SOURCE_APPS: LazyLock<Mutex<HashMap<u32, String>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+async fn session() -> Result<&'static Connection> {
+    Ok(SESSION.get_or_try_init(Connection::session).await?)
+}
+
+/// capture notifications with the configured mode, the server mode falls back to the monitor when
+/// gon can not own `org.freedesktop.Notifications`, the proxy mode fails as the user asked for callbacks
+pub async fn capture(tx: UnboundedSender<Arc<Notification>>, mode: CaptureMode) -> Result<()> {
+    match mode {
+        CaptureMode::Monitor => {},
+        CaptureMode::Server => match server::serve(session().await?, tx.clone(), mode).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("failed to serve notifications, fall back to monitor: {:?}", e),
+        },
+        CaptureMode::Proxy => return server::serve(session().await?, tx, mode).await,
+    }
+
+    let res = notification_listener(tx).await;
+    MONITORING.store(false, Ordering::Relaxed);
+    res
+}
+
+/// set while the monitor receives the `Notify` calls
+static MONITORING: AtomicBool = AtomicBool::new(false);
+
+pub fn capture_status() -> Option<String> {
+    match serving() {
+        Some(Serving::Server) => Some(format!("serving {}", INTERFACE)),
+        Some(Serving::Proxy(upstream)) => Some(format!("proxying {} to {}", INTERFACE, upstream)),
+        None if MONITORING.load(Ordering::Relaxed) => Some("monitoring the session bus".to_string()),
+        None => None,
+    }
+}
+
+/// the bus gives up on a method call after 25 seconds by default
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// whether the actions of and replies to the captured notifications reach the applications which sent them
+pub fn callbacks_supported() -> bool {
+    serving().is_some()
+}
+
+async fn notification_listener(tx: UnboundedSender<Arc<Notification>>) -> Result<()> {
     let connection = Connection::session().await?;
     let monitor_proxy = MonitoringProxy::new(&connection).await?;
 
     let rule = MatchRule::builder()
         .msg_type(Type::MethodCall)
-        .path("/org/freedesktop/Notifications")?
-        .interface("org.freedesktop.Notifications")?
+        .path(PATH)?
+        .interface(INTERFACE)?
         .member("Notify")?
         .build();
 
-    monitor_proxy.become_monitor(&[rule], 0).await?;
+    // the reply of the server carries the id it assigned to the notification
+    let reply_rule = MatchRule::builder()
+        .msg_type(Type::MethodReturn)
+        .sender(INTERFACE)?
+        .build();
+
+    let error_rule = MatchRule::builder()
+        .msg_type(Type::Error)
+        .sender(INTERFACE)?
+        .build();
+
+    monitor_proxy.become_monitor(&[rule, reply_rule, error_rule], 0).await?;
 
-    println!("ready to listen notifications");
+    info!("ready to listen notifications");
+    warn!("actions and inline replies of the captured notifications are not forwarded in monitor capture mode");
+    MONITORING.store(true, Ordering::Relaxed);
     let mut stream = MessageStream::from(&connection);
+
+    // notifications waiting for the server reply, by (sender, serial) of the Notify call
+    let mut pending: HashMap<(String, u32), (Notification, Instant)> = HashMap::new();
     loop {
-        if let Some(Ok(msg)) = stream.next().await {
-            match parse_notification(&msg).await {
-                Ok(notification) => {
-                    tx.send(Arc::new(notification))?;
-                },
-                Err(e) => eprintln!("parse notification error: {:?}", e),
-            }
+        let Some(Ok(msg)) = stream.next().await else {
+            continue;
+        };
+
+        let header = msg.header();
+        match msg.message_type() {
+            Type::MethodCall => {
+                let Some(sender) = header.sender() else {
+                    continue;
+                };
+
+                // the reply of a call may never be seen, the caller timed out or the server is gone
+                pending.retain(|_, (_, called)| called.elapsed() < REPLY_TIMEOUT);
+
+                match parse_notification(&msg).await {
+                    Ok(None) => {},
+                    Ok(Some(notification)) => {
+                        let key = (sender.to_string(), header.primary().serial_num().get());
+                        pending.insert(key, (notification, Instant::now()));
+                    },
+                    Err(e) => error!("parse notification error: {:?}", e),
+                }
+            },
+            Type::MethodReturn | Type::Error => {
+                let (Some(destination), Some(serial)) = (header.destination(), header.reply_serial()) else {
+                    continue;
+                };
+
+                let Some((mut notification, _)) = pending.remove(&(destination.to_string(), serial.get())) else {
+                    continue;
+                };
+
+                let Ok(id) = msg.body().deserialize::<u32>() else {
+                    continue;
+                };
+
+                // applications drop `ActionInvoked` and `NotificationReplied` unless they come from
+                // the owner of the name, which the monitor is not, so actions and replies would do nothing
+                notification.actions.clear();
+                notification.source_id = id;
+                SOURCE_APPS.lock().await.insert(id, destination.to_string());
+                tx.send(Arc::new(notification))?;
+            },
+            _ => {},
         }
     }
 }
 
-async fn parse_notification(msg: &Message) -> Result<Notification> {
-    let body = msg.body();
-    let body: zbus::zvariant::Structure = body.deserialize()?;
-    let fields = body.fields();
+/// action key, and server capability, of the notifications an user can reply to from the popup
+const INLINE_REPLY: &str = "inline-reply";
+
+static CAPABILITIES: OnceCell<Vec<String>> = OnceCell::const_new();
+
+/// hint set on the notifications forwarded by gon, so they are not captured again
+const FORWARDED_HINT: &str = "x-gon-forwarded";
+
+/// close reasons of the `NotificationClosed` signal
+const CLOSED_EXPIRED: u32 = 1;
+const CLOSED_DISMISSED: u32 = 2;
+const CLOSED_BY_CALL: u32 = 3;
+
+/// follow the signals of the notification server and report the ones which concern another device:
+/// actions invoked on, replies to and dismissal of the notifications shown on this node go back to their source,
+/// closing a notification captured on this node goes to the host.
+///
+/// an application calling `CloseNotification` makes the server emit `NotificationClosed` as well,
+/// so both ways of closing a captured notification are covered here.
+pub async fn signal_listener(tx: UnboundedSender<NotificationEvent>) -> Result<()> {
+    // only the signals of the name owner, a proxied server keeps emitting its own
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender(INTERFACE)?
+        .path(PATH)?
+        .interface(INTERFACE)?
+        .build();
+
+    let mut stream = MessageStream::for_match_rule(rule, session().await?, None).await?;
+    while let Some(Ok(msg)) = stream.next().await {
+        let header = msg.header();
+        let Some(member) = header.member() else {
+            continue;
+        };
+
+        match member.as_str() {
+            "ActionInvoked" => {
+                let Ok((id, action_key)) = msg.body().deserialize::<(u32, String)>() else {
+                    continue;
+                };
+
+                let Some((Some(origin), notification_id)) = DISPLAYED.lock().await.remote.get(&id).cloned() else {
+                    continue;
+                };
+
+                tx.send(NotificationEvent::ActionInvoked { origin, notification_id, action_key })?;
+            },
+            "NotificationReplied" => {
+                let Ok((id, text)) = msg.body().deserialize::<(u32, String)>() else {
+                    continue;
+                };
+
+                let Some((Some(origin), notification_id)) = DISPLAYED.lock().await.remote.get(&id).cloned() else {
+                    continue;
+                };
+
+                tx.send(NotificationEvent::Replied { origin, notification_id, text })?;
+            },
+            "NotificationClosed" => {
+                let Ok((id, reason)) = msg.body().deserialize::<(u32, u32)>() else {
+                    continue;
+                };
+
+                SOURCE_APPS.lock().await.remove(&id);
+                let captured = super::captured_closed(id).await;
+                let displayed = DISPLAYED.lock().await.remove(id);
+                if let Some((origin, notification_id)) = displayed {
+                    // only a dismissal by the user goes back, closing by call comes from the source itself
+                    if reason == CLOSED_DISMISSED {
+                        tx.send(NotificationEvent::Closed { origin, notification_id })?;
+                    }
+                } else if let Some(notification_id) = captured
+                    && reason != CLOSED_EXPIRED
+                {
+                    tx.send(NotificationEvent::Closed { origin: None, notification_id })?;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+/// emit `ActionInvoked` to the application which sent the notification,
+/// only gon owning `org.freedesktop.Notifications` is listened to
+pub async fn invoke_action(notification_id: u32, action_key: &str) -> Result<()> {
+    if serving().is_none() {
+        return Err(anyhow!("actions are only forwarded in server or proxy capture mode"));
+    }
+
+    let app = SOURCE_APPS
+        .lock()
+        .await
+        .get(&notification_id)
+        .cloned()
+        .ok_or(anyhow!("unknown notification {}", notification_id))?;
+
+    session()
+        .await?
+        .emit_signal(Some(app.as_str()), PATH, INTERFACE, "ActionInvoked", &(notification_id, action_key))
+        .await?;
+
+    Ok(())
+}
+
+/// emit `NotificationReplied` to the application which sent the notification,
+/// only gon owning `org.freedesktop.Notifications` is listened to
+pub async fn reply(notification_id: u32, text: &str) -> Result<()> {
+    if serving().is_none() {
+        return Err(anyhow!("replies are only forwarded in server or proxy capture mode"));
+    }
+
+    let app = SOURCE_APPS
+        .lock()
+        .await
+        .get(&notification_id)
+        .cloned()
+        .ok_or(anyhow!("unknown notification {}", notification_id))?;
+
+    session()
+        .await?
+        .emit_signal(Some(app.as_str()), PATH, INTERFACE, "NotificationReplied", &(notification_id, text))
+        .await?;
+
+    Ok(())
+}
+
+/// close a notification captured on this node, on behalf of the host
+pub async fn close_captured(notification_id: u32) -> Result<()> {
+    SOURCE_APPS.lock().await.remove(&notification_id);
+    close(notification_id).await
+}
 
-    use zbus::zvariant::Value;
-    let [Value::Str(app_name), _, Value::Str(icon), Value::Str(title), Value::Str(message), ..] = fields else {
-        return Err(anyhow!("is not notification"));
+/// close the copy shown on this node of a notification coming from another device
+pub async fn close_displayed(notification_id: &NotificationId) -> Result<()> {
+    let mut displayed = DISPLAYED.lock().await;
+    let id = displayed
+        .shown
+        .remove(notification_id)
+        .ok_or(anyhow!("notification {} is not displayed", notification_id))?;
+    displayed.remote.remove(&id);
+    drop(displayed);
+
+    close(id).await
+}
+
+async fn capabilities() -> Result<&'static Vec<String>> {
+    CAPABILITIES
+        .get_or_try_init(|| async {
+            let reply = session()
+                .await?
+                .call_method(Some(INTERFACE), PATH, Some(INTERFACE), "GetCapabilities", &())
+                .await?;
+
+            Ok(reply.body().deserialize::<Vec<String>>()?)
+        })
+        .await
+}
+
+async fn close(id: u32) -> Result<()> {
+    let session = session().await?;
+    match serving() {
+        Some(Serving::Server) => server::close(session, id).await?,
+        Some(Serving::Proxy(upstream)) => {
+            session
+                .call_method(Some(upstream.as_str()), PATH, Some(INTERFACE), "CloseNotification", &(id,))
+                .await?;
+        },
+        None => {
+            session
+                .call_method(Some(INTERFACE), PATH, Some(INTERFACE), "CloseNotification", &(id,))
+                .await?;
+        },
+    }
+
+    Ok(())
+}
+
+type NotifyArgs = (String, u32, String, String, String, Vec<String>, HashMap<String, OwnedValue>, i32);
+
+async fn parse_notification(msg: &Message) -> Result<Option<Notification>> {
+    let args: NotifyArgs = msg
+        .body()
+        .deserialize()
+        .map_err(|_| anyhow!("is not notification"))?;
+
+    parse_notify_args(args).await
+}
+
+async fn parse_notify_args(args: NotifyArgs) -> Result<Option<Notification>> {
+    let (app_name, replaces_id, icon, title, message, actions, mut hints, expire_timeout) = args;
+
+    if hints.contains_key(FORWARDED_HINT) {
+        return Ok(None);
+    }
+
+    // actions are sent as a flat list of (key, label) pairs
+    let actions = actions
+        .chunks_exact(2)
+        .map(|pair| NotificationAction {
+            key: pair[0].clone(),
+            label: pair[1].clone(),
+        })
+        .collect();
+
+    let hint_str = |key: &str| {
+        hints
+            .get(key)
+            .and_then(|v| v.downcast_ref::<&str>().ok())
+            .map(str::to_string)
+    };
+
+    let urgency = hints
+        .get("urgency")
+        .and_then(|v| v.downcast_ref::<u8>().ok())
+        .map(Urgency::from)
+        .unwrap_or_default();
+
+    let category = hint_str("category");
+    let desktop_entry = hint_str("desktop-entry");
+
+    let image = if CONFIG.attachments.enabled {
+        read_attachment(&mut hints).await.unwrap_or_else(|e| {
+            warn!("drop attachment of {}: {:?}", app_name, e);
+            None
+        })
+    } else {
+        None
     };
 
-    Ok(Notification {
-        app_id: app_name.to_string(),
-        app_name: app_name.to_string(),
-        title: title.to_string(),
-        message: message.to_string(),
-        icon: read_icon(icon).await,
+    Ok(Some(Notification {
+        id: NotificationId::default(),
+        source_id: 0,
+        replaces: None,
+        origin: None,
+        app_id: app_name.clone(),
+        app_name,
+        title,
+        message,
+        icon: read_icon(&icon).await,
+        icon_hash: None,
+        image,
         timestamp: SystemTime::now(),
-    })
+        replaces_id,
+        actions,
+        urgency,
+        category,
+        desktop_entry,
+        expire_timeout,
+    }))
+}
+
+type ImageData = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+
+/// image attached with the `image-data` hint, or one of its deprecated names, else with `image-path`
+async fn read_attachment(hints: &mut HashMap<String, OwnedValue>) -> Result<Option<Vec<u8>>> {
+    let config = &CONFIG.attachments;
+
+    if let Some(data) = ["image-data", "image_data", "icon_data"].iter().find_map(|key| hints.remove(*key)) {
+        let (width, height, rowstride, has_alpha, bits_per_sample, channels, data) = ImageData::try_from(data)?;
+        let raw = RawImage { width, height, rowstride, has_alpha, bits_per_sample, channels, data };
+        return attachment::from_raw(raw, config).await.map(Some);
+    }
+
+    let Some(path) = ["image-path", "image_path"]
+        .iter()
+        .find_map(|key| hints.get(*key).and_then(|v| v.downcast_ref::<&str>().ok()))
+    else {
+        return Ok(None);
+    };
+
+    // the hint can also be the name of a themed icon, which is not worth forwarding
+    let path = Path::new(path.strip_prefix("file://").unwrap_or(path));
+    if !path.is_absolute() {
+        return Ok(None);
+    }
+
+    attachment::from_file(path.to_path_buf(), config).await.map(Some)
 }
 
 async fn read_icon(icon: &str) -> Option<Vec<u8>> {
     let path: PathBuf = lookup(icon)
+        .with_size(ICON_SIZE as u16)
         .with_cache()
         .find()
         .or_else(|| fs::exists(icon)
             .ok()
             .and_then(|exists| exists.then_some(PathBuf::from(icon))))?;
 
-    tokio::fs::read(path).await.ok()
+    let data = tokio::fs::read(&path).await.ok()?;
+    match normalize_icon(data).await {
+        Ok(icon) => Some(icon),
+        Err(e) => {
+            warn!("drop unsupported icon {}: {:?}", path.display(), e);
+            None
+        },
+    }
+}
+
+#[derive(Default)]
+struct DisplayedNotifications {
+    /// host id of every forwarded notification shown on this node
+    shown: HashMap<NotificationId, u32>,
+    /// origin and id of the forwarded notifications by host id
+    remote: HashMap<u32, (Option<SocketAddr>, NotificationId)>,
+}
+
+impl DisplayedNotifications {
+    fn remove(&mut self, id: u32) -> Option<(Option<SocketAddr>, NotificationId)> {
+        let (origin, notification_id) = self.remote.remove(&id)?;
+        self.shown.remove(&notification_id);
+        Some((origin, notification_id))
+    }
+}
+
+static DISPLAYED: LazyLock<Mutex<DisplayedNotifications>> = LazyLock::new(|| Mutex::new(DisplayedNotifications::default()));
+
+pub async fn send_notification(notify: Notification) -> Result<()> {
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname(&notify.app_name)
+        .summary(&notify.title)
+        .body(&notify.message)
+        .urgency(match notify.urgency {
+            Urgency::Low => notify_rust::Urgency::Low,
+            Urgency::Normal => notify_rust::Urgency::Normal,
+            Urgency::Critical => notify_rust::Urgency::Critical,
+        })
+        .timeout(notify.expire_timeout);
+
+    // a reply button alone would only open the application on the source device
+    let inline_reply = capabilities().await.is_ok_and(|caps| caps.iter().any(|cap| cap == INLINE_REPLY));
+    for action in &notify.actions {
+        if action.key == INLINE_REPLY && !inline_reply {
+            continue;
+        }
+
+        notification.action(&action.key, &action.label);
+    }
+
+    notification.hint(Hint::Custom(FORWARDED_HINT.to_string(), "true".to_string()));
+
+    // servers load app icons by name or path, icons are referenced from the cache
+    let icon = match (&notify.icon, &notify.icon_hash) {
+        (Some(icon), _) => match cache_icon(icon) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!("failed to cache icon of {}: {:?}", notify.app_name, e);
+                None
+            },
+        },
+        (None, Some(hash)) => cached_icon(hash),
+        (None, None) => None,
+    };
+
+    if let Some(path) = icon {
+        notification.icon(&path.to_string_lossy());
+    }
+
+    match notify.image.as_deref().map(cache_attachment) {
+        Some(Ok(path)) => {
+            notification.image_path(&path.to_string_lossy());
+        },
+        Some(Err(e)) => warn!("failed to cache attachment of {}: {:?}", notify.app_name, e),
+        None => {},
+    }
+
+    if let Some(category) = notify.category {
+        notification.hint(Hint::Category(category));
+    }
+
+    if let Some(desktop_entry) = notify.desktop_entry {
+        notification.hint(Hint::DesktopEntry(desktop_entry));
+    }
+
+    if let Some(replaces) = &notify.replaces
+        && let Some(id) = DISPLAYED.lock().await.shown.remove(replaces)
+    {
+        notification.id(id);
+    }
+
+    let handle = notification.show_async().await?;
+    let mut displayed = DISPLAYED.lock().await;
+    displayed.shown.insert(notify.id.clone(), handle.id());
+    displayed.remote.insert(handle.id(), (notify.origin, notify.id));
+
+    Ok(())
 }
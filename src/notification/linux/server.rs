@@ -0,0 +1,493 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, PoisonError, RwLock}};
+
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_stream::StreamExt;
+use zbus::{
+    connection::Connection,
+    fdo::{self, DBusProxy, RequestNameFlags, RequestNameReply},
+    interface,
+    message::{Header, Type},
+    object_server::SignalEmitter,
+    zvariant::OwnedValue,
+    MatchRule, MessageStream,
+};
+
+use crate::config::CaptureMode;
+
+use super::{parse_notify_args, Notification, CLOSED_BY_CALL, INLINE_REPLY, INTERFACE, PATH, SOURCE_APPS};
+
+/// how this node owns `org.freedesktop.Notifications`, unset in monitor mode
+#[derive(Clone)]
+pub enum Serving {
+    Server,
+    /// unique bus name of the notification server gon took over
+    Proxy(String),
+}
+
+/// a proxy turns into a server when the server it took over exits
+static SERVING: RwLock<Option<Serving>> = RwLock::new(None);
+
+pub fn serving() -> Option<Serving> {
+    SERVING.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+fn set_serving(serving: Serving) {
+    *SERVING.write().unwrap_or_else(PoisonError::into_inner) = Some(serving);
+}
+
+struct NotificationServer {
+    tx: UnboundedSender<Arc<Notification>>,
+    upstream: Option<String>,
+    next_id: u32,
+    /// ids of the notifications open in server mode, the ones `replaces_id` may refer to, oldest first
+    open: VecDeque<u32>,
+}
+
+/// nothing is displayed in server mode, notifications no application closes are forgotten after this many
+const OPEN_CAPACITY: usize = 1024;
+
+impl NotificationServer {
+    /// the next id not in use, never 0
+    fn allocate_id(&mut self) -> u32 {
+        let id = loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+            if id != 0 && !self.open.contains(&id) {
+                break id;
+            }
+        };
+
+        if self.open.len() >= OPEN_CAPACITY {
+            self.open.pop_front();
+        }
+        self.open.push_back(id);
+        id
+    }
+
+    /// forget the notification, false when it is not open
+    fn remove(&mut self, id: u32) -> bool {
+        let Some(index) = self.open.iter().position(|open| *open == id) else {
+            return false;
+        };
+
+        self.open.remove(index);
+        true
+    }
+}
+
+/// take `org.freedesktop.Notifications` on the session bus and capture every `Notify` call,
+/// in proxy mode the calls are passed to the server which owned the name before
+pub async fn serve(
+    connection: &'static Connection,
+    tx: UnboundedSender<Arc<Notification>>,
+    mode: CaptureMode,
+) -> Result<()> {
+    let upstream = if mode == CaptureMode::Proxy {
+        let owner = DBusProxy::new(connection)
+            .await?
+            .get_name_owner(INTERFACE.try_into()?)
+            .await
+            .context("no notification server to proxy")?;
+
+        Some(owner.to_string())
+    } else {
+        None
+    };
+
+    let server = NotificationServer {
+        tx,
+        upstream: upstream.clone(),
+        next_id: 1,
+        open: VecDeque::new(),
+    };
+    connection.object_server().at(PATH, server).await?;
+
+    // a server which does not allow replacement keeps the name, DoNotQueue makes it fail right away
+    let flags = if upstream.is_some() {
+        RequestNameFlags::ReplaceExisting | RequestNameFlags::DoNotQueue
+    } else {
+        RequestNameFlags::DoNotQueue.into()
+    };
+    let reply = connection.request_name_with_flags(INTERFACE, flags).await?;
+    if !matches!(reply, RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner) {
+        connection.object_server().remove::<NotificationServer, _>(PATH).await?;
+        return match upstream {
+            Some(upstream) => Err(anyhow!(
+                "{} does not allow replacing it as {}, proxy mode needs a server which does",
+                upstream,
+                INTERFACE
+            )),
+            None => Err(anyhow!("{} is owned by another notification server", INTERFACE)),
+        };
+    }
+
+    match upstream {
+        Some(upstream) => {
+            info!("proxy notifications to {}", upstream);
+            set_serving(Serving::Proxy(upstream.clone()));
+
+            // most servers exit once they lose the name
+            let watched = upstream.clone();
+            tokio::spawn(async move {
+                if let Err(e) = watch_upstream(connection, watched).await {
+                    error!("watch proxied notification server error: {:?}", e);
+                }
+            });
+
+            tokio::spawn(async move {
+                if let Err(e) = relay_signals(connection, upstream).await {
+                    error!("relay notification signals error: {:?}", e);
+                }
+            });
+        },
+        None => {
+            info!("serving notifications");
+            set_serving(Serving::Server);
+        },
+    }
+
+    Ok(())
+}
+
+/// serve the notifications alone once the proxied server left the bus, nothing would answer them otherwise
+async fn watch_upstream(connection: &Connection, upstream: String) -> Result<()> {
+    let mut changes = DBusProxy::new(connection)
+        .await?
+        .receive_name_owner_changed_with_args(&[(0, upstream.as_str())])
+        .await?;
+
+    while let Some(change) = changes.next().await {
+        if change.args()?.new_owner().is_some() {
+            continue;
+        }
+
+        warn!("{} left the bus, serve notifications without displaying them", upstream);
+        let server = connection.object_server().interface::<_, NotificationServer>(PATH).await?;
+        server.get_mut().await.upstream = None;
+        set_serving(Serving::Server);
+        break;
+    }
+
+    Ok(())
+}
+
+/// close a notification shown by gon in server mode, as if the application called `CloseNotification`
+pub async fn close(connection: &Connection, id: u32) -> Result<()> {
+    let server = connection.object_server().interface::<_, NotificationServer>(PATH).await?;
+    if server.get_mut().await.remove(id) {
+        NotificationServer::notification_closed(server.signal_emitter(), id, CLOSED_BY_CALL).await?;
+    }
+
+    Ok(())
+}
+
+/// emit the signals of the proxied server as ours, applications only listen to the name owner
+async fn relay_signals(connection: &Connection, upstream: String) -> Result<()> {
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender(upstream.as_str())?
+        .path(PATH)?
+        .interface(INTERFACE)?
+        .build();
+
+    let mut stream = MessageStream::for_match_rule(rule, connection, None).await?;
+    while let Some(Ok(msg)) = stream.next().await {
+        let header = msg.header();
+        let Some(member) = header.member() else {
+            continue;
+        };
+
+        let body = msg.body();
+        let res = match member.as_str() {
+            "NotificationClosed" => match body.deserialize::<(u32, u32)>() {
+                Ok(args) => connection.emit_signal(None::<&str>, PATH, INTERFACE, member, &args).await,
+                Err(e) => Err(e),
+            },
+            "ActionInvoked" | "NotificationReplied" | "ActivationToken" => match body.deserialize::<(u32, String)>() {
+                Ok(args) => connection.emit_signal(None::<&str>, PATH, INTERFACE, member, &args).await,
+                Err(e) => Err(e),
+            },
+            _ => continue,
+        };
+
+        if let Err(e) = res {
+            error!("failed to relay {}: {:?}", member, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn failed(e: impl std::fmt::Display) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+#[interface(name = "org.freedesktop.Notifications")]
+impl NotificationServer {
+    #[allow(clippy::too_many_arguments)]
+    async fn notify(
+        &mut self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> fdo::Result<u32> {
+        let id = match &self.upstream {
+            Some(upstream) => connection
+                .call_method(
+                    Some(upstream.as_str()),
+                    PATH,
+                    Some(INTERFACE),
+                    "Notify",
+                    &(&app_name, replaces_id, &app_icon, &summary, &body, &actions, &hints, expire_timeout),
+                )
+                .await?
+                .body()
+                .deserialize::<u32>()
+                .inspect(|id| {
+                    // the ids stay unique if the proxied server leaves
+                    if let Some(next) = id.checked_add(1) {
+                        self.next_id = self.next_id.max(next);
+                    }
+                })?,
+            // an unknown `replaces_id` gets a new id, as the specification asks
+            None if self.open.contains(&replaces_id) => replaces_id,
+            None => self.allocate_id(),
+        };
+
+        let replaces_id = if id == replaces_id { replaces_id } else { 0 };
+        let args = (app_name, replaces_id, app_icon, summary, body, actions, hints, expire_timeout);
+        match parse_notify_args(args).await {
+            Ok(Some(mut notification)) => {
+                notification.source_id = id;
+                if let Some(sender) = header.sender() {
+                    SOURCE_APPS.lock().await.insert(id, sender.to_string());
+                }
+
+                self.tx.send(Arc::new(notification)).map_err(failed)?;
+            },
+            Ok(None) => {},
+            Err(e) => error!("parse notification error: {:?}", e),
+        }
+
+        Ok(id)
+    }
+
+    async fn close_notification(
+        &mut self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        id: u32,
+    ) -> fdo::Result<()> {
+        match &self.upstream {
+            Some(upstream) => {
+                connection
+                    .call_method(Some(upstream.as_str()), PATH, Some(INTERFACE), "CloseNotification", &(id,))
+                    .await?;
+            },
+            None => {
+                if self.remove(id) {
+                    Self::notification_closed(&emitter, id, CLOSED_BY_CALL).await?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn get_capabilities(&self, #[zbus(connection)] connection: &Connection) -> fdo::Result<Vec<String>> {
+        match &self.upstream {
+            Some(upstream) => Ok(connection
+                .call_method(Some(upstream.as_str()), PATH, Some(INTERFACE), "GetCapabilities", &())
+                .await?
+                .body()
+                .deserialize()?),
+            None => Ok(["actions", "body", "persistence", INLINE_REPLY].map(String::from).to_vec()),
+        }
+    }
+
+    #[zbus(out_args("name", "vendor", "version", "spec_version"))]
+    async fn get_server_information(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<(String, String, String, String)> {
+        match &self.upstream {
+            Some(upstream) => Ok(connection
+                .call_method(Some(upstream.as_str()), PATH, Some(INTERFACE), "GetServerInformation", &())
+                .await?
+                .body()
+                .deserialize()?),
+            None => Ok((
+                "gon".to_string(),
+                "ckaznable".to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+                "1.2".to_string(),
+            )),
+        }
+    }
+
+    #[zbus(signal)]
+    async fn notification_closed(emitter: &SignalEmitter<'_>, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+
+    use anyhow::{anyhow, Context, Result};
+    use tokio::{sync::mpsc::unbounded_channel, time::timeout};
+    use tokio_stream::StreamExt;
+    use zbus::{
+        connection::Builder, fdo::RequestNameFlags, interface, message::Type, zvariant::Value, Connection, MatchRule,
+        MessageStream,
+    };
+
+    use super::{serve, CLOSED_BY_CALL, INTERFACE, PATH};
+    use crate::config::CaptureMode;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// a `dbus-daemon` of its own, killed with the test
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> std::io::Result<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address)?;
+
+            Ok(Self { daemon, address: address.trim().to_string() })
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    async fn notify(app: &Connection, replaces_id: u32, title: &str) -> Result<u32> {
+        let hints: HashMap<&str, Value> = HashMap::new();
+        let args = ("test", replaces_id, "", title, "message", Vec::<&str>::new(), hints, -1i32);
+        let reply = app.call_method(Some(INTERFACE), PATH, Some(INTERFACE), "Notify", &args).await?;
+        Ok(reply.body().deserialize()?)
+    }
+
+    #[tokio::test]
+    async fn server_mode_captures_notify_and_closes() -> Result<()> {
+        let bus = PrivateBus::start().context("the test needs dbus-daemon")?;
+
+        let connection = Box::leak(Box::new(Builder::address(bus.address.as_str())?.build().await?));
+
+        let (tx, mut rx) = unbounded_channel();
+        serve(connection, tx, CaptureMode::Server).await?;
+
+        let app = Builder::address(bus.address.as_str())?.build().await?;
+        let id = notify(&app, 0, "title").await?;
+
+        let notification = timeout(WAIT, rx.recv()).await?.ok_or(anyhow!("nothing captured"))?;
+        assert_eq!(notification.source_id, id);
+        assert_eq!(notification.app_name, "test");
+        assert_eq!(notification.title, "title");
+        assert_eq!(notification.message, "message");
+
+        // a known id is replaced, an unknown one gets a new id
+        assert_eq!(notify(&app, id, "replaced").await?, id);
+        let unknown = notify(&app, 999, "unknown").await?;
+        assert_ne!(unknown, 999);
+        assert_ne!(unknown, id);
+
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .interface(INTERFACE)?
+            .member("NotificationClosed")?
+            .build();
+        let mut signals = MessageStream::for_match_rule(rule, &app, None).await?;
+
+        app.call_method(Some(INTERFACE), PATH, Some(INTERFACE), "CloseNotification", &(id,))
+            .await?;
+
+        let signal = timeout(WAIT, signals.next()).await?.ok_or(anyhow!("no signal"))??;
+        assert_eq!(signal.body().deserialize::<(u32, u32)>()?, (id, CLOSED_BY_CALL));
+
+        Ok(())
+    }
+
+    /// a notification server which allows being replaced, as proxy mode needs
+    struct Upstream;
+
+    const UPSTREAM_ID: u32 = 42;
+
+    #[interface(name = "org.freedesktop.Notifications")]
+    impl Upstream {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            _app_name: String,
+            _replaces_id: u32,
+            _app_icon: String,
+            _summary: String,
+            _body: String,
+            _actions: Vec<String>,
+            _hints: HashMap<String, zbus::zvariant::OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            UPSTREAM_ID
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_mode_serves_alone_once_upstream_leaves() -> Result<()> {
+        let bus = PrivateBus::start().context("the test needs dbus-daemon")?;
+
+        let upstream = Builder::address(bus.address.as_str())?.build().await?;
+        upstream.object_server().at(PATH, Upstream).await?;
+        upstream.request_name_with_flags(INTERFACE, RequestNameFlags::AllowReplacement.into()).await?;
+
+        let connection = Box::leak(Box::new(Builder::address(bus.address.as_str())?.build().await?));
+        let (tx, mut rx) = unbounded_channel();
+        serve(connection, tx, CaptureMode::Proxy).await?;
+
+        let app = Builder::address(bus.address.as_str())?.build().await?;
+        assert_eq!(notify(&app, 0, "proxied").await?, UPSTREAM_ID);
+        let notification = timeout(WAIT, rx.recv()).await?.ok_or(anyhow!("nothing captured"))?;
+        assert_eq!(notification.source_id, UPSTREAM_ID);
+
+        // the replaced server exits, gon answers on its own with ids following the proxied ones
+        upstream.close().await?;
+        let id = timeout(WAIT, async {
+            loop {
+                if let Ok(id) = notify(&app, 0, "served").await {
+                    return id;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        assert_eq!(id, UPSTREAM_ID + 1);
+
+        Ok(())
+    }
+}
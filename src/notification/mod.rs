@@ -4,85 +4,387 @@ mod windows;
 #[cfg(target_os = "linux")]
 mod linux;
 
+pub mod history;
+pub mod icon;
+pub mod sink;
+pub mod source;
+
+#[cfg(target_os = "linux")]
+mod attachment;
+
+use anyhow::Result;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::{sync::Arc, time::SystemTime};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use futures::{stream::SelectAll, StreamExt};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver},
+    Mutex,
+};
+
+use crate::{CONFIG, DEVICE_ID};
+use source::NotificationStream;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Urgency {
+    #[serde(alias = "low")]
+    Low,
+    #[default]
+    #[serde(alias = "normal")]
+    Normal,
+    #[serde(alias = "critical")]
+    Critical,
+}
+
+impl From<u8> for Urgency {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Urgency::Low,
+            2 => Urgency::Critical,
+            _ => Urgency::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationAction {
+    pub key: String,
+    pub label: String,
+}
+
+/// globally unique id of a notification, given by the device which captured it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NotificationId {
+    pub device: String,
+    pub seq: u64,
+}
+
+impl Display for NotificationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.device, self.seq)
+    }
+}
+
+// start from the current time so ids stay unique across restarts
+static SEQ: LazyLock<AtomicU64> = LazyLock::new(|| {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+
+    AtomicU64::new(now)
+});
+
+impl NotificationId {
+    /// a new id for a notification captured on this node
+    pub fn next() -> Self {
+        Self {
+            device: DEVICE_ID.clone(),
+            seq: SEQ.fetch_add(1, Ordering::Relaxed) + 1,
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.device == *DEVICE_ID
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Notification {
+    /// assigned by `SystemNotificationListener` when captured
+    pub id: NotificationId,
+    /// id assigned by the notification system of the source device, 0 if unknown
+    pub source_id: u32,
+    /// id of the notification this one replaces, resolved from `replaces_id` on the source device
+    pub replaces: Option<NotificationId>,
+    /// node address of the source device, set when forwarded to the host
+    pub origin: Option<SocketAddr>,
     pub app_id: String,
     pub app_name: String,
+    /// normalized icon, only kept on the source device, the host fetches it by `icon_hash` when not cached
     pub icon: Option<Vec<u8>>,
+    pub icon_hash: Option<String>,
+    /// PNG image attached to the notification
+    pub image: Option<Vec<u8>>,
     pub title: String,
     pub message: String,
     pub timestamp: SystemTime,
+    /// id of the notification this one replaces on the source device, 0 if none
+    pub replaces_id: u32,
+    pub actions: Vec<NotificationAction>,
+    pub urgency: Urgency,
+    pub category: Option<String>,
+    pub desktop_entry: Option<String>,
+    /// milliseconds, -1 means server default and 0 means never expire
+    pub expire_timeout: i32,
 }
 
-pub struct SystemNotificationListener {
-    tx: UnboundedSender<Arc<Notification>>,
-    rx: UnboundedReceiver<Arc<Notification>>,
+impl Notification {
+    /// a notification without any of the optional fields
+    pub fn new(app_name: String, title: String, message: String) -> Self {
+        Self {
+            id: NotificationId::default(),
+            source_id: 0,
+            replaces: None,
+            origin: None,
+            app_id: app_name.clone(),
+            app_name,
+            icon: None,
+            icon_hash: None,
+            image: None,
+            title,
+            message,
+            timestamp: SystemTime::now(),
+            replaces_id: 0,
+            actions: Vec::new(),
+            urgency: Urgency::default(),
+            category: None,
+            desktop_entry: None,
+            expire_timeout: -1,
+        }
+    }
+}
+
+/// interactions with notifications shown on this node which have to go back to the source device
+#[derive(Debug, Clone)]
+pub enum NotificationEvent {
+    ActionInvoked {
+        origin: SocketAddr,
+        notification_id: NotificationId,
+        action_key: String,
+    },
+    Replied {
+        origin: SocketAddr,
+        notification_id: NotificationId,
+        text: String,
+    },
+    /// `origin` is None when the notification was captured on this node
+    Closed {
+        origin: Option<SocketAddr>,
+        notification_id: NotificationId,
+    },
 }
 
-impl Default for SystemNotificationListener {
-    fn default() -> Self {
-        let (tx, rx) = unbounded_channel();
+/// notifications captured on this node which are still open
+#[derive(Default)]
+struct CapturedNotifications {
+    by_id: HashMap<NotificationId, u32>,
+    by_source_id: HashMap<u32, NotificationId>,
+    /// ids in capture order, the oldest are forgotten past `CAPTURED_CAPACITY`
+    /// as closing is not reported on every platform
+    order: VecDeque<NotificationId>,
+}
+
+const CAPTURED_CAPACITY: usize = 1024;
+
+impl CapturedNotifications {
+    /// returns the id of the notification replaced by this one
+    fn insert(&mut self, id: NotificationId, source_id: u32) -> Option<NotificationId> {
+        let replaced = self.by_source_id.insert(source_id, id.clone());
+        if let Some(replaced) = &replaced {
+            self.by_id.remove(replaced);
+        }
 
-        SystemNotificationListener {
-            tx,
-            rx,
+        self.by_id.insert(id.clone(), source_id);
+        self.order.push_back(id);
+        while self.order.len() > CAPTURED_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.remove(&oldest);
+            }
         }
+
+        replaced
+    }
+
+    fn remove(&mut self, id: &NotificationId) -> Option<u32> {
+        let source_id = self.by_id.remove(id)?;
+        self.by_source_id.remove(&source_id);
+        Some(source_id)
+    }
+
+    fn remove_source_id(&mut self, source_id: u32) -> Option<NotificationId> {
+        let id = self.by_source_id.remove(&source_id)?;
+        self.by_id.remove(&id);
+        Some(id)
     }
 }
 
+static CAPTURED: LazyLock<Mutex<CapturedNotifications>> = LazyLock::new(|| Mutex::new(CapturedNotifications::default()));
+
+/// captures the notifications of every configured source
+#[derive(Default)]
+pub struct SystemNotificationListener {
+    streams: SelectAll<NotificationStream>,
+}
+
 impl SystemNotificationListener {
-    pub fn listen(&self) {
-        let tx = self.tx.clone();
+    pub fn listen(&mut self) -> UnboundedReceiver<NotificationEvent> {
+        for config in &CONFIG.sources {
+            let source = match source::from_config(config) {
+                Ok(source) => source,
+                Err(e) => {
+                    error!("failed to start {:?} notification source: {:?}", config, e);
+                    continue;
+                },
+            };
 
-        #[cfg(target_os = "windows")]
-        {
-            std::thread::spawn(move || {
-                let rt = tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .unwrap();
-
-                rt.block_on(async {
-                    if let Err(e) = windows::notification_listener(tx).await {
-                        eprintln!("Windows notification listener error: {:?}", e);
-                    }
-                });
-            });
+            let name = source.name();
+            match source.start() {
+                Ok(stream) => {
+                    info!("start {} notification source", name);
+                    self.streams.push(stream);
+                },
+                Err(e) => error!("failed to start {} notification source: {:?}", name, e),
+            }
         }
 
+        #[allow(unused_variables)]
+        let (event_tx, event_rx) = unbounded_channel();
+
         #[allow(clippy::let_underscore_future)]
         #[cfg(target_os = "linux")]
         {
             let _ = tokio::spawn(async move {
-                let _ = linux::notification_listener(tx).await;
+                if let Err(e) = linux::signal_listener(event_tx).await {
+                    error!("Linux signal listener error: {:?}", e);
+                }
             });
         }
+
+        event_rx
     }
 
     pub async fn next_notify(&mut self) -> Option<Arc<Notification>> {
-        self.rx.recv().await
+        let mut notif = self.streams.next().await?;
+        let id = NotificationId::next();
+
+        // only the system source gives ids which can be referred to later
+        let replaced = if notif.source_id != 0 {
+            CAPTURED.lock().await.insert(id.clone(), notif.source_id)
+        } else {
+            None
+        };
+
+        let notif_mut = Arc::make_mut(&mut notif);
+        notif_mut.id = id;
+        notif_mut.icon_hash = notif_mut.icon.as_deref().map(icon::icon_hash);
+        if notif_mut.replaces_id != 0 {
+            notif_mut.replaces = replaced;
+        }
+
+        Some(notif)
     }
 }
 
-pub async fn send_notification(notify: Notification) {
+/// id of the notification captured on this node under `source_id`, forgetting it once closed
+async fn captured_closed(source_id: u32) -> Option<NotificationId> {
+    CAPTURED.lock().await.remove_source_id(source_id)
+}
+
+async fn captured_source_id(id: &NotificationId) -> Option<u32> {
+    CAPTURED.lock().await.by_id.get(id).copied()
+}
+
+/// how the system notifications are captured, None when they are not
+pub fn capture_status() -> Option<String> {
+    #[cfg(target_os = "windows")]
+    return Some("user notification listener".to_string());
+
+    #[cfg(target_os = "linux")]
+    return linux::capture_status();
+}
+
+/// whether actions invoked and replies typed on the host reach the applications which sent the notifications captured here
+pub fn callbacks_supported() -> bool {
+    #[cfg(target_os = "windows")]
+    return false;
+
+    #[cfg(target_os = "linux")]
+    return linux::callbacks_supported();
+}
+
+/// display the notification on this device
+pub async fn send_notification(notify: Notification) -> Result<()> {
     #[cfg(target_os = "windows")]
+    windows::send_notification(&notify.title, &notify.message, false)?;
+
+    #[cfg(target_os = "linux")]
+    linux::send_notification(notify).await?;
+
+    Ok(())
+}
+
+/// invoke an action of a notification captured on this node, on behalf of the host
+pub async fn invoke_action(notification_id: NotificationId, action_key: String) {
+    let Some(source_id) = captured_source_id(&notification_id).await else {
+        warn!("unknown notification {}", notification_id);
+        return;
+    };
+
+    #[cfg(target_os = "windows")]
+    {
+        warn!("invoking action {} of notification {} is not supported on Windows", action_key, source_id);
+    }
+
+    #[cfg(target_os = "linux")]
     {
-        if let Err(e) = windows::send_notification(&notify.title, &notify.message, false) {
-            eprintln!("Windows notification send error: {:?}", e);
+        if let Err(e) = linux::invoke_action(source_id, &action_key).await {
+            error!("Linux invoke action error: {:?}", e);
         }
     }
+}
+
+/// deliver a reply typed on the host to a notification captured on this node
+pub async fn reply(notification_id: NotificationId, text: String) {
+    let Some(source_id) = captured_source_id(&notification_id).await else {
+        warn!("unknown notification {}", notification_id);
+        return;
+    };
+
+    #[cfg(target_os = "windows")]
+    {
+        let _ = text;
+        warn!("replying to notification {} is not supported on Windows", source_id);
+    }
 
     #[cfg(target_os = "linux")]
     {
-        // TODO add icon for linux
-        let _ = notify_rust::Notification::new()
-            .summary(&notify.title)
-            .body(&notify.message)
-            .show_async()
-            .await;
+        if let Err(e) = linux::reply(source_id, &text).await {
+            error!("Linux reply error: {:?}", e);
+        }
+    }
+}
+
+/// close a notification, the one captured here when it comes from this node, the copy shown here otherwise
+pub async fn close_notification(notification_id: NotificationId) {
+    #[cfg(target_os = "windows")]
+    {
+        warn!("closing notification {} is not supported on Windows", notification_id);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let res = if notification_id.is_local() {
+            let source_id = CAPTURED.lock().await.remove(&notification_id);
+            match source_id {
+                Some(source_id) => linux::close_captured(source_id).await,
+                None => Ok(()),
+            }
+        } else {
+            linux::close_displayed(&notification_id).await
+        };
+
+        if let Err(e) = res {
+            error!("Linux close notification error: {:?}", e);
+        }
     }
 }
@@ -0,0 +1,257 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    path::PathBuf,
+    sync::{Arc, LazyLock, OnceLock},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use log::{debug, error, info};
+use futures::{future::BoxFuture, FutureExt};
+use serde::Serialize;
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    config::{SinkConfig, SinkFilter, SinkKind},
+    events::{self, NodeEvent},
+    metrics, CONFIG, DIRS,
+};
+
+use super::{Notification, NotificationId, Urgency};
+
+#[cfg(unix)]
+mod audit;
+mod exec;
+#[cfg(unix)]
+mod ipc;
+mod webhook;
+
+/// somewhere the host outputs the notifications it receives
+pub trait NotificationSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>>;
+}
+
+pub fn from_config(config: &SinkConfig) -> Result<Arc<dyn NotificationSink>> {
+    Ok(match &config.kind {
+        SinkKind::Desktop => Arc::new(DesktopSink),
+        SinkKind::Log { path } => {
+            let path = path.clone().unwrap_or_else(|| DIRS.data_dir().join("notifications.log"));
+            Arc::new(LogSink::open(path)?)
+        },
+        #[cfg(unix)]
+        SinkKind::Ipc { path } => {
+            let path = path.clone().unwrap_or_else(|| {
+                DIRS.runtime_dir().unwrap_or_else(|| DIRS.cache_dir()).join("gon.sock")
+            });
+            Arc::new(ipc::IpcSink::bind(path)?)
+        },
+        #[cfg(not(unix))]
+        SinkKind::Ipc { .. } => return Err(anyhow::anyhow!("ipc sink is not supported on this platform")),
+        SinkKind::Webhook(webhook) => {
+            let dead_letter = webhook
+                .dead_letter
+                .clone()
+                .unwrap_or_else(|| DIRS.data_dir().join("webhook-dead-letter.log"));
+            Arc::new(webhook::WebhookSink::new(webhook, dead_letter)?)
+        },
+        SinkKind::Exec(exec) => Arc::new(exec::ExecSink::new(exec)?),
+        SinkKind::Mqtt => Arc::new(MqttSink),
+        #[cfg(unix)]
+        SinkKind::Audit { target, socket } => Arc::new(audit::AuditSink::new(*target, socket.clone())),
+        #[cfg(not(unix))]
+        SinkKind::Audit { .. } => return Err(anyhow::anyhow!("audit sink is not supported on this platform")),
+    })
+}
+
+/// flat JSON view of a notification, as written by the sinks
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationRecord {
+    pub id: String,
+    pub device: String,
+    pub app_id: String,
+    pub app_name: String,
+    pub title: String,
+    pub message: String,
+    pub urgency: Urgency,
+    pub category: Option<String>,
+    /// unix time in seconds
+    pub timestamp: u64,
+}
+
+impl From<&Notification> for NotificationRecord {
+    fn from(notification: &Notification) -> Self {
+        Self {
+            id: notification.id.to_string(),
+            device: notification.id.device.clone(),
+            app_id: notification.app_id.clone(),
+            app_name: notification.app_name.clone(),
+            title: notification.title.clone(),
+            message: notification.message.clone(),
+            urgency: notification.urgency,
+            category: notification.category.clone(),
+            timestamp: notification
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl SinkFilter {
+    pub fn matches(&self, notification: &Notification) -> bool {
+        let listed = |apps: &[String]| {
+            apps.iter()
+                .any(|app| *app == notification.app_name || *app == notification.app_id)
+        };
+
+        (self.apps.is_empty() || listed(&self.apps))
+            && !listed(&self.exclude_apps)
+            && (self.devices.is_empty() || self.devices.contains(&notification.id.device))
+            && self.min_urgency.is_none_or(|urgency| notification.urgency >= urgency)
+    }
+}
+
+struct Sink {
+    inner: Arc<dyn NotificationSink>,
+    filter: SinkFilter,
+}
+
+static SINKS: OnceLock<Vec<Sink>> = OnceLock::new();
+
+/// ids of the notifications lately dispatched on this node, to drop the ones received twice
+static RECENT: LazyLock<Mutex<VecDeque<NotificationId>>> = LazyLock::new(|| Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)));
+const RECENT_CAPACITY: usize = 256;
+
+/// start the sinks of the config, a sink failing to start is left out
+pub fn start() {
+    let sinks = CONFIG
+        .sinks
+        .iter()
+        .filter_map(|config| match from_config(config) {
+            Ok(inner) => {
+                info!("start {} notification sink", inner.name());
+                Some(Sink {
+                    inner,
+                    filter: config.filter.clone(),
+                })
+            },
+            Err(e) => {
+                error!("failed to start {:?} notification sink: {:?}", config.kind, e);
+                None
+            },
+        })
+        .collect();
+
+    let _ = SINKS.set(sinks);
+}
+
+/// hand a notification received by the host to every sink whose filter matches it
+pub async fn dispatch(notification: Notification) {
+    {
+        let mut recent = RECENT.lock().await;
+        if recent.contains(&notification.id) {
+            debug!("drop duplicated notification {}", notification.id);
+            metrics::DROPPED_DUPLICATE.inc();
+            return;
+        }
+
+        if recent.len() == RECENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(notification.id.clone());
+    }
+
+    metrics::RECEIVED.inc();
+    let record = NotificationRecord::from(&notification);
+    super::history::push(record.clone()).await;
+    events::emit(NodeEvent::Notification(record));
+
+    let notification = Arc::new(notification);
+    for sink in SINKS.get().into_iter().flatten() {
+        if !sink.filter.matches(&notification) {
+            continue;
+        }
+
+        // a slow or failing sink does not hold back the others
+        let inner = sink.inner.clone();
+        let notification = notification.clone();
+        tokio::spawn(async move {
+            if let Err(e) = inner.send(notification).await {
+                metrics::SINK_ERRORS.inc();
+                error!("{} sink error: {:?}", inner.name(), e);
+            }
+        });
+    }
+}
+
+/// the popup of the desktop
+struct DesktopSink;
+
+impl NotificationSink for DesktopSink {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move {
+            super::send_notification(Arc::unwrap_or_clone(notification)).await?;
+            metrics::DISPLAYED.inc();
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// one JSON record per line appended to a file
+struct LogSink {
+    file: Mutex<File>,
+}
+
+impl LogSink {
+    fn open(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(File::from_std(file)),
+        })
+    }
+}
+
+impl NotificationSink for LogSink {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let mut line = serde_json::to_string(&NotificationRecord::from(&*notification))?;
+            line.push('\n');
+
+            let mut file = self.file.lock().await;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// publishes through the MQTT bridge
+struct MqttSink;
+
+impl NotificationSink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move { crate::mqtt::publish(&NotificationRecord::from(&*notification)).await }.boxed()
+    }
+}
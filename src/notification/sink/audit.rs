@@ -0,0 +1,187 @@
+use std::{path::PathBuf, sync::Arc, time::UNIX_EPOCH};
+
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use tokio::net::UnixDatagram;
+
+use crate::{config::AuditTarget, daemon::misc::sha256_hex};
+
+use super::{Notification, NotificationSink};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// LOG_INFO
+const PRIORITY: u8 = 6;
+/// LOG_USER
+const FACILITY: u8 = 1;
+
+/// one record per notification which crossed the network, the body itself is only kept as a hash
+pub struct AuditSink {
+    target: AuditTarget,
+    socket: PathBuf,
+}
+
+impl AuditSink {
+    pub fn new(target: AuditTarget, socket: Option<PathBuf>) -> Self {
+        let socket = socket.unwrap_or_else(|| match target {
+            AuditTarget::Journald => JOURNALD_SOCKET.into(),
+            AuditTarget::Syslog => SYSLOG_SOCKET.into(),
+        });
+
+        Self { target, socket }
+    }
+}
+
+struct AuditRecord {
+    id: String,
+    device: String,
+    app: String,
+    title: String,
+    body_sha256: String,
+    origin: String,
+    /// unix time in seconds
+    time: u64,
+}
+
+impl From<&Notification> for AuditRecord {
+    fn from(notification: &Notification) -> Self {
+        Self {
+            id: notification.id.to_string(),
+            device: notification.id.device.clone(),
+            app: notification.app_name.clone(),
+            title: notification.title.clone(),
+            body_sha256: sha256_hex(notification.message.as_bytes()),
+            origin: notification.origin.map(|origin| origin.to_string()).unwrap_or_default(),
+            time: notification
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl AuditRecord {
+    fn summary(&self) -> String {
+        format!("notification {} from {} of {}", self.id, self.device, self.app)
+    }
+
+    /// native journal protocol, values with a newline are length prefixed
+    fn journald(&self) -> Vec<u8> {
+        let fields = [
+            ("MESSAGE", self.summary()),
+            ("PRIORITY", PRIORITY.to_string()),
+            ("SYSLOG_FACILITY", FACILITY.to_string()),
+            ("SYSLOG_IDENTIFIER", "gon".to_string()),
+            ("GON_NOTIFICATION_ID", self.id.clone()),
+            ("GON_DEVICE", self.device.clone()),
+            ("GON_APP", self.app.clone()),
+            ("GON_TITLE", self.title.clone()),
+            ("GON_BODY_SHA256", self.body_sha256.clone()),
+            ("GON_ORIGIN", self.origin.clone()),
+            ("GON_TIME", self.time.to_string()),
+        ];
+
+        let mut datagram = Vec::new();
+        for (key, value) in fields {
+            datagram.extend_from_slice(key.as_bytes());
+            if value.contains('\n') {
+                datagram.push(b'\n');
+                datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                datagram.push(b'=');
+            }
+            datagram.extend_from_slice(value.as_bytes());
+            datagram.push(b'\n');
+        }
+
+        datagram
+    }
+
+    /// BSD syslog line, the daemon adds the time and host
+    fn syslog(&self) -> Vec<u8> {
+        let quote = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', " "));
+
+        format!(
+            "<{}>gon[{}]: {} id={} device={} app={} title={} body_sha256={} origin={} time={}",
+            FACILITY * 8 + PRIORITY,
+            std::process::id(),
+            self.summary(),
+            self.id,
+            self.device,
+            quote(&self.app),
+            quote(&self.title),
+            self.body_sha256,
+            self.origin,
+            self.time,
+        )
+        .into_bytes()
+    }
+}
+
+impl NotificationSink for AuditSink {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let record = AuditRecord::from(&*notification);
+            let datagram = match self.target {
+                AuditTarget::Journald => record.journald(),
+                AuditTarget::Syslog => record.syslog(),
+            };
+
+            let socket = UnixDatagram::unbound()?;
+            socket.send_to(&datagram, &self.socket).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditRecord;
+
+    fn record(title: &str) -> AuditRecord {
+        AuditRecord {
+            id: "laptop-7".to_string(),
+            device: "laptop".to_string(),
+            app: "Mail".to_string(),
+            title: title.to_string(),
+            body_sha256: "ab12".to_string(),
+            origin: "192.168.1.5:5000".to_string(),
+            time: 1700000000,
+        }
+    }
+
+    #[test]
+    fn formats_syslog_lines() {
+        let line = String::from_utf8(record("a \"quoted\"\nline").syslog()).unwrap();
+        assert_eq!(
+            line,
+            format!(
+                "<14>gon[{}]: notification laptop-7 from laptop of Mail id=laptop-7 device=laptop app=\"Mail\" \
+                 title=\"a \\\"quoted\\\" line\" body_sha256=ab12 origin=192.168.1.5:5000 time=1700000000",
+                std::process::id()
+            )
+        );
+    }
+
+    #[test]
+    fn formats_journald_fields() {
+        let datagram = record("two\nlines").journald();
+        let text = String::from_utf8_lossy(&datagram);
+        assert!(text.starts_with("MESSAGE=notification laptop-7 from laptop of Mail\nPRIORITY=6\nSYSLOG_FACILITY=1\n"));
+        assert!(text.contains("\nGON_APP=Mail\n"));
+        assert!(text.ends_with("\nGON_TIME=1700000000\n"));
+
+        // a value with a newline is written as its length in little endian and the raw bytes
+        let mut title = b"\nGON_TITLE\n".to_vec();
+        title.extend_from_slice(&9u64.to_le_bytes());
+        title.extend_from_slice(b"two\nlines\n");
+        assert!(datagram.windows(title.len()).any(|window| window == title));
+    }
+}
@@ -0,0 +1,139 @@
+use std::{process::Stdio, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use log::info;
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::Value;
+use tokio::{io::AsyncWriteExt, process::Command, sync::Semaphore};
+
+use crate::config::ExecConfig;
+
+use super::{Notification, NotificationRecord, NotificationSink};
+
+/// runs a command per notification, the fields of the record are given as `GON_<FIELD>`
+/// environment variables and the record as JSON on stdin
+pub struct ExecSink {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    /// commands running at the same time, the others wait for a permit
+    permits: Semaphore,
+}
+
+impl ExecSink {
+    pub fn new(config: &ExecConfig) -> Result<Self> {
+        let (program, args) = config.command.split_first().ok_or(anyhow!("exec sink without command"))?;
+
+        Ok(Self {
+            program: program.clone(),
+            args: args.to_vec(),
+            timeout: Duration::from_secs(config.timeout),
+            permits: Semaphore::new(config.concurrency.max(1)),
+        })
+    }
+
+    async fn run(&self, record: &NotificationRecord) -> Result<()> {
+        let json = serde_json::to_string(record)?;
+
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .envs(env_vars(record)?)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            // also kills the command when it times out
+            .kill_on_drop(true);
+
+        let mut child = command.spawn()?;
+        let stdin = child.stdin.take();
+        let write = async move {
+            if let Some(mut stdin) = stdin {
+                // the command may exit without reading stdin
+                let _ = stdin.write_all(json.as_bytes()).await;
+            }
+        };
+
+        // stderr is drained while writing, a command filling it before reading stdin would block
+        // the write, and a command which neither reads stdin nor exits is stopped by the timeout
+        let run = async move {
+            let ((), output) = tokio::join!(write, child.wait_with_output());
+            output
+        };
+
+        let output = tokio::time::timeout(self.timeout, run)
+            .await
+            .map_err(|_| anyhow!("{} timed out after {:?}", self.program, self.timeout))??;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("{} exited with {}: {}", self.program, output.status, stderr.trim()));
+        }
+
+        info!("{} exited with {} for notification {}", self.program, output.status, record.id);
+        Ok(())
+    }
+}
+
+impl NotificationSink for ExecSink {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let _permit = self.permits.acquire().await?;
+            self.run(&NotificationRecord::from(&*notification)).await
+        }
+        .boxed()
+    }
+}
+
+fn env_vars(record: &NotificationRecord) -> Result<Vec<(String, String)>> {
+    let Value::Object(fields) = serde_json::to_value(record)? else {
+        return Ok(Vec::new());
+    };
+
+    Ok(fields
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s,
+                Value::Null => String::new(),
+                other => other.to_string(),
+            };
+
+            (format!("GON_{}", key.to_uppercase()), value)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{ExecSink, NotificationSink};
+    use crate::{config::ExecConfig, notification::Notification};
+
+    #[tokio::test]
+    async fn drains_stderr_while_writing_stdin() {
+        let config = ExecConfig {
+            command: ["sh", "-c", "head -c 200000 /dev/zero >&2; cat >/dev/null; exit 3"].map(String::from).to_vec(),
+            timeout: 10,
+            concurrency: 1,
+        };
+        let sink = ExecSink::new(&config).unwrap();
+
+        // a record larger than the pipe buffer
+        let message = "x".repeat(100_000);
+        let notification = Arc::new(Notification::new("Backup".to_string(), "Done".to_string(), message));
+
+        let start = Instant::now();
+        let err = sink.send(notification).await.unwrap_err();
+        assert!(err.to_string().contains("exited with"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,89 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use log::{error, info, warn};
+use futures::{future::BoxFuture, FutureExt};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{UnixListener, UnixStream},
+    sync::broadcast::{self, error::RecvError},
+};
+
+use super::{Notification, NotificationRecord, NotificationSink};
+
+/// records kept for subscribers which fall behind
+const IPC_BUFFER: usize = 64;
+/// wait after a failed accept, doubled while it keeps failing, e.g. when out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(10);
+
+/// JSON records written line by line to every process connected to a unix socket
+pub struct IpcSink {
+    tx: broadcast::Sender<Arc<str>>,
+}
+
+impl IpcSink {
+    pub fn bind(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // the socket left by a previous run
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        info!("ipc sink listening on {}", path.display());
+
+        let (tx, _) = broadcast::channel(IPC_BUFFER);
+        let subscribers = tx.clone();
+        tokio::spawn(async move {
+            let mut backoff = ACCEPT_BACKOFF;
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        backoff = ACCEPT_BACKOFF;
+                        tokio::spawn(subscribe(stream, subscribers.subscribe()));
+                    },
+                    Err(e) => {
+                        error!("ipc sink accept error, retrying in {:?}: {:?}", backoff, e);
+                        tokio::time::sleep(backoff).await;
+                        backoff = backoff.saturating_mul(2).min(MAX_ACCEPT_BACKOFF);
+                    },
+                }
+            }
+        });
+
+        Ok(Self { tx })
+    }
+}
+
+async fn subscribe(mut stream: UnixStream, mut rx: broadcast::Receiver<Arc<str>>) {
+    loop {
+        match rx.recv().await {
+            Ok(line) => {
+                if stream.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            },
+            Err(RecvError::Lagged(n)) => warn!("ipc subscriber lagged, {} notifications dropped", n),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+impl NotificationSink for IpcSink {
+    fn name(&self) -> &'static str {
+        "ipc"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let mut line = serde_json::to_string(&NotificationRecord::from(&*notification))?;
+            line.push('\n');
+
+            // no subscriber is not an error
+            let _ = self.tx.send(line.into());
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,334 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use futures::{future::BoxFuture, FutureExt};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    Client, Method, StatusCode, Url,
+};
+use serde_json::{json, Value};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+
+use crate::config::WebhookConfig;
+
+use super::{Notification, NotificationRecord, NotificationSink};
+
+/// longest wait between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// sends every notification as an HTTP request, by default a POST of the JSON record
+pub struct WebhookSink {
+    client: Client,
+    method: Method,
+    url: Url,
+    template: Option<String>,
+    retries: u32,
+    backoff: Duration,
+    /// locked while writing so concurrent failures do not interleave
+    dead_letter: Mutex<PathBuf>,
+}
+
+impl WebhookSink {
+    pub fn new(config: &WebhookConfig, dead_letter: PathBuf) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (name, value) in &config.headers {
+            headers.insert(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+        }
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+
+        Ok(Self {
+            client,
+            method: Method::from_bytes(config.method.to_uppercase().as_bytes())?,
+            url: Url::parse(&config.url)?,
+            template: config.template.clone(),
+            retries: config.retries,
+            backoff: Duration::from_millis(config.backoff).min(MAX_BACKOFF),
+            dead_letter: Mutex::new(dead_letter),
+        })
+    }
+
+    /// send the body, retrying with an exponential backoff on network errors, 5xx and 429
+    async fn deliver(&self, body: &str) -> Result<()> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+
+        loop {
+            let res = self
+                .client
+                .request(self.method.clone(), self.url.clone())
+                .body(body.to_string())
+                .send()
+                .await;
+
+            let err = match res {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                // the endpoint refused the request, sending it again would not help
+                Ok(res) if res.status().is_client_error() && res.status() != StatusCode::TOO_MANY_REQUESTS => {
+                    return Err(anyhow!("{} responded {}", self.url, res.status()));
+                },
+                Ok(res) => anyhow!("{} responded {}", self.url, res.status()),
+                Err(e) => e.into(),
+            };
+
+            if attempt >= self.retries {
+                return Err(err);
+            }
+
+            attempt += 1;
+            warn!("webhook delivery failed, retry {}/{} in {:?}: {:?}", attempt, self.retries, backoff, err);
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// keep a notification which could not be delivered, one JSON entry per line
+    async fn write_dead_letter(&self, record: &NotificationRecord, body: &str, error: &anyhow::Error) -> Result<()> {
+        let mut line = json!({
+            "url": self.url.as_str(),
+            "error": format!("{:#}", error),
+            "body": body,
+            "notification": record,
+        })
+        .to_string();
+        line.push('\n');
+
+        let path = self.dead_letter.lock().await;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&*path).await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+impl NotificationSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send(&self, notification: Arc<Notification>) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let record = NotificationRecord::from(&*notification);
+            let body = match &self.template {
+                Some(template) => render(template, &record)?,
+                None => serde_json::to_string(&record)?,
+            };
+
+            if let Err(e) = self.deliver(&body).await {
+                self.write_dead_letter(&record, &body, &e).await?;
+                return Err(e);
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// replace the `{{field}}` placeholders of the template by the fields of the record in one pass,
+/// so values are never expanded again, unknown placeholders are kept as they are,
+/// strings are JSON escaped without quotes so they can be put inside a JSON string
+pub fn render(template: &str, record: &NotificationRecord) -> Result<String> {
+    let Value::Object(fields) = serde_json::to_value(record)? else {
+        return Ok(template.to_string());
+    };
+
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        body.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+
+        let Some(value) = fields.get(&rest[2..end]) else {
+            // not a placeholder, a later `{{` may still start one
+            body.push_str("{{");
+            rest = &rest[2..];
+            continue;
+        };
+
+        match value {
+            Value::String(s) => {
+                let quoted = Value::String(s.clone()).to_string();
+                body.push_str(&quoted[1..quoted.len() - 1]);
+            },
+            Value::Null => {},
+            other => body.push_str(&other.to_string()),
+        }
+
+        rest = &rest[end + 2..];
+    }
+    body.push_str(rest);
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, VecDeque},
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use axum::{extract::State, http::StatusCode, Router};
+    use serde_json::Value;
+    use tokio::{net::TcpListener, sync::Mutex};
+
+    use super::{render, NotificationRecord, NotificationSink, WebhookSink};
+    use crate::{
+        config::WebhookConfig,
+        notification::{Notification, NotificationId, Urgency},
+    };
+
+    /// answers with the given statuses in turn, then 200, and keeps every request body
+    #[derive(Clone, Default)]
+    struct Endpoint {
+        statuses: Arc<Mutex<VecDeque<StatusCode>>>,
+        bodies: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Endpoint {
+        async fn start(statuses: &[StatusCode]) -> (Self, SocketAddr) {
+            let endpoint = Self::default();
+            endpoint.statuses.lock().await.extend(statuses);
+
+            let app = Router::new().fallback(Self::handle).with_state(endpoint.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+
+            (endpoint, addr)
+        }
+
+        async fn handle(State(endpoint): State<Self>, body: String) -> StatusCode {
+            endpoint.bodies.lock().await.push(body);
+            endpoint.statuses.lock().await.pop_front().unwrap_or(StatusCode::OK)
+        }
+
+        async fn requests(&self) -> usize {
+            self.bodies.lock().await.len()
+        }
+    }
+
+    fn notification() -> Arc<Notification> {
+        let mut notification = Notification::new("Mail".to_string(), "Hello".to_string(), "a \"quoted\" line".to_string());
+        notification.id = NotificationId { device: "laptop".to_string(), seq: 7 };
+        notification.urgency = Urgency::Critical;
+        Arc::new(notification)
+    }
+
+    fn sink(addr: SocketAddr, name: &str) -> WebhookSink {
+        let config = WebhookConfig {
+            url: format!("http://{}/hook", addr),
+            method: "POST".to_string(),
+            headers: HashMap::new(),
+            template: None,
+            retries: 2,
+            backoff: 20,
+            timeout: 5,
+            dead_letter: None,
+        };
+
+        let dead_letter = std::env::temp_dir().join(format!("gon-test-{}-{}.log", std::process::id(), name));
+        let _ = std::fs::remove_file(&dead_letter);
+        WebhookSink::new(&config, dead_letter).unwrap()
+    }
+
+    async fn dead_letters(sink: &WebhookSink) -> Vec<Value> {
+        let path = sink.dead_letter.lock().await.clone();
+        let lines = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+        let _ = tokio::fs::remove_file(&path).await;
+        lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn delivers_the_record() {
+        let (endpoint, addr) = Endpoint::start(&[StatusCode::NO_CONTENT]).await;
+        let sink = sink(addr, "success");
+
+        sink.send(notification()).await.unwrap();
+
+        let bodies = endpoint.bodies.lock().await.clone();
+        assert_eq!(bodies.len(), 1);
+        let record: Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(record["id"], "laptop-7");
+        assert_eq!(record["message"], "a \"quoted\" line");
+        assert!(dead_letters(&sink).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_with_backoff() {
+        let (endpoint, addr) =
+            Endpoint::start(&[StatusCode::SERVICE_UNAVAILABLE, StatusCode::TOO_MANY_REQUESTS]).await;
+        let sink = sink(addr, "retry");
+
+        let started = Instant::now();
+        sink.send(notification()).await.unwrap();
+
+        assert_eq!(endpoint.requests().await, 3);
+        // 20ms before the first retry, doubled before the second
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        assert!(dead_letters(&sink).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (endpoint, addr) = Endpoint::start(&[StatusCode::BAD_REQUEST]).await;
+        let sink = sink(addr, "client-error");
+
+        assert!(sink.send(notification()).await.is_err());
+
+        assert_eq!(endpoint.requests().await, 1);
+        assert_eq!(dead_letters(&sink).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn writes_a_dead_letter_when_retries_run_out() {
+        let (endpoint, addr) = Endpoint::start(&[StatusCode::INTERNAL_SERVER_ERROR; 3]).await;
+        let sink = sink(addr, "dead-letter");
+
+        let err = sink.send(notification()).await.unwrap_err();
+        assert!(err.to_string().contains("500"));
+        assert_eq!(endpoint.requests().await, 3);
+
+        let letters = dead_letters(&sink).await;
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0]["url"], format!("http://{}/hook", addr));
+        assert_eq!(letters[0]["notification"]["id"], "laptop-7");
+        assert!(letters[0]["error"].as_str().unwrap().contains("500"));
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let record = NotificationRecord::from(&*notification());
+        let body = render(r#"{"text": "{{title}}: {{message}}", "level": "{{urgency}}", "at": {{timestamp}}}"#, &record)
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["text"], "Hello: a \"quoted\" line");
+        assert_eq!(body["level"], "Critical");
+        assert_eq!(body["at"], record.timestamp);
+    }
+
+    #[test]
+    fn renders_values_only_once() {
+        let mut record = NotificationRecord::from(&*notification());
+        record.title = "{{message}}".to_string();
+
+        assert_eq!(render("{{title}} {{category}}", &record).unwrap(), "{{message}} ");
+        assert_eq!(render("{{unknown}} {{ {{app_name}} {{", &record).unwrap(), "{{unknown}} {{ Mail {{");
+    }
+}
@@ -0,0 +1,294 @@
+use std::{path::{Path, PathBuf}, pin::Pin, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use log::{error, warn};
+use futures::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader, SeekFrom},
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::config::SourceConfig;
+
+use super::{Notification, Urgency};
+
+pub type NotificationStream = Pin<Box<dyn Stream<Item = Arc<Notification>> + Send>>;
+
+/// something producing the notifications captured on this node
+pub trait NotificationSource {
+    fn name(&self) -> &'static str;
+
+    /// start capturing, notifications come out of the returned stream
+    fn start(self: Box<Self>) -> Result<NotificationStream>;
+}
+
+pub fn from_config(config: &SourceConfig) -> Result<Box<dyn NotificationSource>> {
+    Ok(match config {
+        SourceConfig::System => Box::new(SystemSource),
+        SourceConfig::Stdin => Box::new(StdinSource),
+        SourceConfig::File { path } => Box::new(FileSource { path: path.clone() }),
+        SourceConfig::Test { interval: 0 } => return Err(anyhow!("test source interval must be at least 1 second")),
+        SourceConfig::Test { interval } => Box::new(TestSource { interval: Duration::from_secs(*interval) }),
+    })
+}
+
+/// notification described in JSON, as read by the stdin and file sources
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationInput {
+    pub app_name: Option<String>,
+    pub title: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub urgency: Urgency,
+    pub category: Option<String>,
+}
+
+impl NotificationInput {
+    /// `default_app` names the application when the input does not
+    pub fn into_notification(self, default_app: &str) -> Notification {
+        let app_name = self.app_name.unwrap_or_else(|| default_app.to_string());
+        let mut notification = Notification::new(app_name, self.title, self.message);
+        notification.urgency = self.urgency;
+        notification.category = self.category;
+        notification
+    }
+}
+
+fn parse_line(line: &str, source: &str) -> Option<Arc<Notification>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+
+    match serde_json::from_str::<NotificationInput>(line) {
+        Ok(input) => Some(Arc::new(input.into_notification(source))),
+        Err(e) => {
+            warn!("invalid notification from {}: {}", source, e);
+            None
+        },
+    }
+}
+
+/// notifications of the desktop, the D-Bus notification server on Linux and
+/// the user notification listener on Windows
+pub struct SystemSource;
+
+impl NotificationSource for SystemSource {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn start(self: Box<Self>) -> Result<NotificationStream> {
+        let (tx, rx) = unbounded_channel();
+        spawn_system_listener(tx);
+        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+    }
+}
+
+#[allow(clippy::let_underscore_future)]
+fn spawn_system_listener(tx: UnboundedSender<Arc<Notification>>) {
+    #[cfg(target_os = "windows")]
+    {
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            rt.block_on(async {
+                if let Err(e) = super::windows::notification_listener(tx).await {
+                    error!("Windows notification listener error: {:?}", e);
+                }
+            });
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let _ = tokio::spawn(async move {
+            if let Err(e) = super::linux::capture(tx, crate::CONFIG.capture.mode).await {
+                error!("Linux notification listener error: {:?}", e);
+            }
+        });
+    }
+}
+
+/// one JSON notification per line on the standard input
+pub struct StdinSource;
+
+impl NotificationSource for StdinSource {
+    fn name(&self) -> &'static str {
+        "stdin"
+    }
+
+    fn start(self: Box<Self>) -> Result<NotificationStream> {
+        let lines = BufReader::new(tokio::io::stdin()).lines();
+        let stream = stream::unfold(lines, |mut lines| async move {
+            match lines.next_line().await {
+                Ok(Some(line)) => Some((parse_line(&line, "stdin"), lines)),
+                Ok(None) => None,
+                Err(e) => {
+                    error!("stdin source error: {:?}", e);
+                    None
+                },
+            }
+        });
+
+        Ok(Box::pin(stream.filter_map(|notif| async move { notif })))
+    }
+}
+
+/// JSON notifications appended line by line to a file, like `echo '{..}' >> file`
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl NotificationSource for FileSource {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn start(self: Box<Self>) -> Result<NotificationStream> {
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(e) = watch_file(self.path, tx).await {
+                error!("file source error: {:?}", e);
+            }
+        });
+
+        Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+    }
+}
+
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// follow the end of the file, only the lines appended after start are read
+async fn watch_file(path: PathBuf, tx: UnboundedSender<Arc<Notification>>) -> Result<()> {
+    let mut offset = tokio::fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+    let mut pending = Vec::new();
+    let mut interval = tokio::time::interval(FILE_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Ok(len) = tokio::fs::metadata(&path).await.map(|m| m.len()) else {
+            continue;
+        };
+
+        // truncated or rotated
+        if len < offset {
+            offset = 0;
+            pending.clear();
+        }
+
+        if len == offset {
+            continue;
+        }
+
+        // the file may be gone again, it is read on the next poll
+        match read_from(&path, offset).await {
+            Ok(buf) => {
+                offset += buf.len() as u64;
+                pending.extend_from_slice(&buf);
+            },
+            Err(e) => {
+                warn!("failed to read {}: {:?}", path.display(), e);
+                continue;
+            },
+        }
+
+        // keep an unterminated last line for the next round
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            if let Some(notif) = parse_line(&String::from_utf8_lossy(&line), "file") {
+                tx.send(notif)?;
+            }
+        }
+    }
+}
+
+async fn read_from(path: &Path, offset: u64) -> Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+/// a notification every `interval`, to try the forwarding without a desktop
+pub struct TestSource {
+    interval: Duration,
+}
+
+impl NotificationSource for TestSource {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn start(self: Box<Self>) -> Result<NotificationStream> {
+        let interval = tokio::time::interval_at(tokio::time::Instant::now() + self.interval, self.interval);
+        let stream = stream::unfold((interval, 1u64), |(mut interval, n)| async move {
+            interval.tick().await;
+
+            let notification = Notification::new(
+                "gon-test".to_string(),
+                "Test notification".to_string(),
+                format!("test notification #{}", n),
+            );
+
+            Some((Arc::new(notification), (interval, n + 1)))
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use tokio::{sync::mpsc::unbounded_channel, time::timeout};
+
+    use super::{from_config, watch_file};
+    use crate::config::SourceConfig;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn append(path: &std::path::Path, line: &[u8]) {
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        file.write_all(line).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_test_source_without_interval() {
+        assert!(from_config(&SourceConfig::Test { interval: 0 }).is_err());
+        assert_eq!(from_config(&SourceConfig::Test { interval: 1 }).unwrap().name(), "test");
+    }
+
+    #[tokio::test]
+    async fn file_source_survives_invalid_data_and_rotation() {
+        let path = std::env::temp_dir().join(format!("gon-test-{}-source.log", std::process::id()));
+        std::fs::write(&path, "{\"title\": \"before start\"}\n").unwrap();
+
+        let (tx, mut rx) = unbounded_channel();
+        let watcher = tokio::spawn(watch_file(path.clone(), tx));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        append(&path, b"{\"title\": \"caf\xe9\"}\n");
+        let notification = timeout(WAIT, rx.recv()).await.unwrap().unwrap();
+        assert_eq!(notification.title, "caf\u{fffd}");
+
+        std::fs::remove_file(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        append(&path, b"{\"title\": \"rotated\"}\n");
+        let notification = timeout(WAIT, rx.recv()).await.unwrap().unwrap();
+        assert_eq!(notification.title, "rotated");
+
+        watcher.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
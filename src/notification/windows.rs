@@ -15,7 +15,8 @@ use windows::{
     },
 };
 
-use super::Notification;
+use super::icon::normalize_icon;
+use super::{Notification, NotificationId, Urgency};
 
 async fn read_logo(display_info: AppDisplayInfo) -> Result<Vec<u8>> {
     let logo_stream = display_info
@@ -29,9 +30,11 @@ async fn read_logo(display_info: AppDisplayInfo) -> Result<Vec<u8>> {
         .await
         .context("awaiting opening for reading failed")?;
 
-    read_stream_to_bytes(logo_stream)
+    let logo = read_stream_to_bytes(logo_stream)
         .await
-        .context("failed to read stream to bytes")
+        .context("failed to read stream to bytes")?;
+
+    normalize_icon(logo).await.context("unsupported logo format")
 }
 
 pub async fn notif_to_message(notif: UserNotification) -> Result<Notification> {
@@ -63,12 +66,24 @@ pub async fn notif_to_message(notif: UserNotification) -> Result<Notification> {
         .fold(String::new(), |a, b| a + &b.to_string() + "\n");
 
     Ok(Notification {
+        id: NotificationId::default(),
+        source_id: notif.Id()?,
+        replaces: None,
+        origin: None,
         app_id,
         app_name,
         icon,
+        icon_hash: None,
+        image: None,
         title,
         message,
         timestamp: SystemTime::now(),
+        replaces_id: 0,
+        actions: Vec::new(),
+        urgency: Urgency::default(),
+        category: None,
+        desktop_entry: None,
+        expire_timeout: -1,
     })
 }
 
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::LazyLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::join_all;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, time::timeout};
+
+use crate::{
+    client::Client,
+    daemon::{misc::device_name, service::NodeProperties},
+    events::{self, NodeEvent},
+    AppMode, DEVICE_ID,
+};
+
+/// peers which have not answered for this long are forgotten
+const PEER_EXPIRY: Duration = Duration::from_secs(5 * 60);
+/// and so are peers failing this many checks in a row
+const MAX_FAILURES: u32 = 3;
+/// how long a peer gets to answer a check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// what a node tells about itself in the handshake
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerInfo {
+    /// address the node listens on
+    pub addr: SocketAddr,
+    pub device_id: String,
+    pub name: String,
+    pub os: String,
+    pub version: String,
+    pub role: String,
+}
+
+impl PeerInfo {
+    pub fn local(addr: SocketAddr, mode: &AppMode<SocketAddr>) -> Self {
+        Self {
+            addr,
+            device_id: DEVICE_ID.clone(),
+            name: device_name(),
+            os: std::env::consts::OS.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            role: if mode.is_host() { "host" } else { "client" }.to_string(),
+        }
+    }
+}
+
+/// a known node, the metadata stays empty until a handshake succeeds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub device_id: Option<String>,
+    pub name: Option<String>,
+    pub os: Option<String>,
+    pub version: Option<String>,
+    pub role: Option<String>,
+    /// unix seconds of the last successful handshake or mDNS announcement
+    pub last_seen: u64,
+    /// checks failed since then
+    pub failures: u32,
+}
+
+impl Peer {
+    /// the name when known, the address otherwise
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.addr.to_string())
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.role.as_deref() == Some("host")
+    }
+}
+
+static PEERS: LazyLock<Mutex<HashMap<SocketAddr, Peer>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// the node at `addr` answered, with its information when it came from a handshake
+pub async fn seen(addr: SocketAddr, info: Option<PeerInfo>) {
+    let mut peers = PEERS.lock().await;
+    let peer = entry(&mut peers, addr);
+
+    peer.last_seen = now();
+    peer.failures = 0;
+    if let Some(info) = info {
+        peer.device_id = Some(info.device_id);
+        peer.name = Some(info.name);
+        peer.os = Some(info.os);
+        peer.version = Some(info.version);
+        peer.role = Some(info.role);
+    }
+}
+
+/// the node at `addr` announced itself over mDNS, the other fields wait for the next check
+pub async fn advertised(addr: SocketAddr, properties: &NodeProperties) {
+    let mut peers = PEERS.lock().await;
+    let peer = entry(&mut peers, addr);
+
+    peer.last_seen = now();
+    peer.device_id = Some(properties.device_id.clone());
+    peer.name = Some(properties.name.clone());
+    peer.role = Some(properties.role.clone());
+}
+
+fn entry(peers: &mut HashMap<SocketAddr, Peer>, addr: SocketAddr) -> &mut Peer {
+    peers.entry(addr).or_insert_with(|| {
+        info!("new peer {}", addr);
+        events::emit(NodeEvent::PeerDiscovered { addr });
+        Peer {
+            addr,
+            device_id: None,
+            name: None,
+            os: None,
+            version: None,
+            role: None,
+            last_seen: 0,
+            failures: 0,
+        }
+    })
+}
+
+/// the node at `addr` did not answer a check
+pub async fn failed(addr: SocketAddr) {
+    if let Some(peer) = PEERS.lock().await.get_mut(&addr) {
+        peer.failures += 1;
+    }
+}
+
+/// forget the node at `addr`
+pub async fn remove(addr: SocketAddr) -> Option<Peer> {
+    let peer = PEERS.lock().await.remove(&addr)?;
+    info!("lost peer {}", addr);
+    events::emit(NodeEvent::PeerLost { addr });
+    Some(peer)
+}
+
+/// forget the peers which stopped answering
+pub async fn expire() {
+    let expired: Vec<SocketAddr> = {
+        let deadline = now().saturating_sub(PEER_EXPIRY.as_secs());
+        PEERS
+            .lock()
+            .await
+            .values()
+            .filter(|peer| peer.failures >= MAX_FAILURES || peer.last_seen < deadline)
+            .map(|peer| peer.addr)
+            .collect()
+    };
+
+    for addr in expired {
+        remove(addr).await;
+    }
+}
+
+/// handshake with every known peer at once, then forget the ones which stopped answering
+pub async fn check(client: Client) {
+    let checks = addrs().await.into_iter().map(|addr| {
+        let client = &client;
+        async move {
+            let hello = async { client.connect(addr).await?.hello().await };
+            match timeout(CHECK_TIMEOUT, hello).await {
+                Ok(Ok(info)) => seen(addr, Some(info)).await,
+                _ => failed(addr).await,
+            }
+        }
+    });
+    join_all(checks).await;
+
+    expire().await;
+}
+
+/// every known peer, ordered by address
+pub async fn list() -> Vec<Peer> {
+    let mut peers: Vec<Peer> = PEERS.lock().await.values().cloned().collect();
+    peers.sort_by_key(|peer| peer.addr);
+    peers
+}
+
+pub async fn addrs() -> Vec<SocketAddr> {
+    PEERS.lock().await.keys().copied().collect()
+}
+
+pub async fn len() -> usize {
+    PEERS.lock().await.len()
+}
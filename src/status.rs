@@ -0,0 +1,256 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, sync::Mutex, time::timeout};
+
+use crate::{
+    client::Client,
+    daemon::{
+        node::Node,
+        protocol::{Message, Method, Payload, Response},
+    },
+    notification,
+    peers::{self, Peer},
+    AppMode, DEVICE_ID, DIRS,
+};
+
+/// how long the host gets to answer a ping
+const HOST_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// what a running daemon knows about itself, answered to `Method::Status`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub device_id: String,
+    pub version: String,
+    pub role: String,
+    pub host: Option<SocketAddr>,
+    /// None when there is no host to ping
+    pub host_reachable: Option<bool>,
+    pub addr: SocketAddr,
+    pub key_fingerprint: String,
+    pub mdns_service: String,
+    pub mdns_running: bool,
+    /// None when system notifications are not captured
+    pub capture: Option<String>,
+    /// whether actions invoked and replies typed on the host reach the applications on this device
+    #[serde(default)]
+    pub callbacks: bool,
+    /// error of encrypting and decrypting a test frame
+    pub self_test: Option<String>,
+    pub peers: Vec<Peer>,
+}
+
+/// where the daemon writes its node address for the commands
+pub fn addr_path() -> PathBuf {
+    DIRS.runtime_dir().unwrap_or(DIRS.cache_dir()).join("node.addr")
+}
+
+pub fn write_addr(addr: SocketAddr) -> Result<()> {
+    let path = addr_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, addr.to_string())?;
+    Ok(())
+}
+
+pub async fn report(
+    node: Arc<Node<Response>>,
+    mode: AppMode<SocketAddr>,
+    mdns_service: String,
+    mdns_running: bool,
+) -> StatusReport {
+    let host = mode.get_host().copied();
+    let host_reachable = match host {
+        Some(host) => {
+            let client = Client::new(node.clone(), Arc::new(Mutex::new(mode.clone())));
+            let ping = async { client.connect(host).await.ok()?.ping().await.then_some(()) };
+            Some(matches!(timeout(HOST_PING_TIMEOUT, ping).await, Ok(Some(()))))
+        },
+        None => None,
+    };
+
+    StatusReport {
+        device_id: DEVICE_ID.clone(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        role: if mode.is_host() { "host" } else { "client" }.to_string(),
+        host,
+        host_reachable,
+        addr: node.addr(),
+        key_fingerprint: node.key_fingerprint().to_string(),
+        mdns_service,
+        mdns_running,
+        capture: notification::capture_status(),
+        callbacks: notification::callbacks_supported(),
+        self_test: node.self_test().err().map(|e| e.to_string()),
+        peers: peers::list().await,
+    }
+}
+
+/// ask the daemon running on this device for its report
+async fn request_report(node: &Node<Response>) -> Result<StatusReport> {
+    let addr: SocketAddr = fs::read_to_string(addr_path())
+        .map_err(|_| anyhow!("daemon is not running"))?
+        .trim()
+        .parse()?;
+
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| anyhow!("daemon is not running on {}: {}", addr, e))?;
+
+    let res = node
+        .send_and_wait_response(&mut stream, Message { method: Method::Status, payload: Payload::Empty })
+        .await?;
+
+    match res.result {
+        Some(Payload::Text(json)) if !res.is_failed() => Ok(serde_json::from_str(&json)?),
+        _ => Err(anyhow!("daemon did not answer the status")),
+    }
+}
+
+fn since(secs: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    format!("{}s ago", now.saturating_sub(secs))
+}
+
+/// print the state of the running daemon
+pub async fn status(json: bool) -> Result<()> {
+    let node = Node::<Response>::new().await?;
+    let report = request_report(&node).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("device:      {} (gon {})", report.device_id, report.version);
+    println!("role:        {}", report.role);
+    match (report.host, report.host_reachable) {
+        (Some(host), Some(true)) => println!("host:        {} (reachable)", host),
+        (Some(host), _) => println!("host:        {} (unreachable)", host),
+        (None, _) if report.role == "host" => println!("host:        this device"),
+        (None, _) => println!("host:        not found"),
+    }
+    println!("address:     {}", report.addr);
+    println!("key:         {}", report.key_fingerprint);
+    println!(
+        "mdns:        {} ({})",
+        report.mdns_service,
+        if report.mdns_running { "running" } else { "stopped" }
+    );
+    println!("capture:     {}", report.capture.as_deref().unwrap_or("inactive"));
+    if report.capture.is_some() && !report.callbacks {
+        println!("             actions and replies are not forwarded to the applications");
+    }
+    println!("peers:       {}", report.peers.len());
+    print_peers(&report.peers);
+
+    Ok(())
+}
+
+/// print the nodes known by the running daemon
+pub async fn peers(json: bool) -> Result<()> {
+    let node = Node::<Response>::new().await?;
+    let report = request_report(&node).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report.peers)?);
+    } else {
+        print_peers(&report.peers);
+    }
+
+    Ok(())
+}
+
+fn print_peers(peers: &[Peer]) {
+    for peer in peers {
+        let unknown = || "?".to_string();
+        println!(
+            "  {} {} ({}, {} on {}, gon {}) last seen {}{}",
+            peer.addr,
+            peer.label(),
+            peer.role.clone().unwrap_or_else(unknown),
+            peer.device_id.clone().unwrap_or_else(unknown),
+            peer.os.clone().unwrap_or_else(unknown),
+            peer.version.clone().unwrap_or_else(unknown),
+            since(peer.last_seen),
+            if peer.failures > 0 { format!(", {} failed checks", peer.failures) } else { String::new() },
+        );
+    }
+}
+
+fn check(ok: bool, name: &str, detail: impl std::fmt::Display) -> bool {
+    println!("[{}] {}: {}", if ok { " ok " } else { "fail" }, name, detail);
+    ok
+}
+
+/// a limitation which does not fail the checks
+fn note(name: &str, detail: impl std::fmt::Display) {
+    println!("[note] {}: {}", name, detail);
+}
+
+const CALLBACKS_NOTE: &str = "actions and replies are not forwarded to the applications, set capture.mode to \"server\" or \"proxy\"";
+
+/// run the self-checks, fails when one of them does
+pub async fn doctor() -> Result<()> {
+    let mut ok = true;
+
+    let node = match Node::<Response>::new().await {
+        Ok(node) => {
+            check(true, "bind", node.addr());
+            node
+        },
+        Err(e) => {
+            check(false, "bind", e);
+            return Err(anyhow!("checks failed"));
+        },
+    };
+
+    ok &= match node.self_test() {
+        Ok(()) => check(true, "decrypt", format!("test frame with key {}", node.key_fingerprint())),
+        Err(e) => check(false, "decrypt", e),
+    };
+
+    match request_report(&node).await {
+        Ok(report) => {
+            check(true, "daemon", report.addr);
+            if report.key_fingerprint != node.key_fingerprint() {
+                ok &= check(false, "key", "the daemon uses another key, restart it");
+            }
+
+            if let Some(e) = &report.self_test {
+                ok &= check(false, "daemon decrypt", e);
+            }
+
+            ok &= check(report.mdns_running, "mdns", &report.mdns_service);
+            ok &= match &report.capture {
+                Some(capture) => check(true, "capture", capture),
+                None => check(false, "capture", "system notifications are not captured"),
+            };
+            if report.capture.is_some() && !report.callbacks {
+                note("callbacks", CALLBACKS_NOTE);
+            }
+            ok &= match (report.host, report.host_reachable) {
+                (_, _) if report.role == "host" => check(true, "host", "this device"),
+                (Some(host), Some(true)) => check(true, "host", host),
+                (Some(host), _) => check(false, "host", format!("{} does not answer", host)),
+                (None, _) => check(false, "host", "no host found"),
+            };
+        },
+        Err(e) => ok &= check(false, "daemon", e),
+    }
+
+    if ok {
+        Ok(())
+    } else {
+        Err(anyhow!("checks failed"))
+    }
+}
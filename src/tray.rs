@@ -1,8 +1,17 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::OnceLock};
 
+use log::error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tray_item::{TrayItem, IconSource};
 
+use crate::peers::Peer;
+
+/// peers named in the tray menu, the others are counted
+const MAX_TRAY_PEERS: usize = 3;
+
+/// id of the menu item listing the peers
+static PEERS_ITEM: OnceLock<u32> = OnceLock::new();
+
 #[derive(Debug, Clone, Copy)]
 pub enum TrayEvent {
     BecomeHost,
@@ -58,6 +67,11 @@ pub fn init_tray() -> (TrayItem, Receiver<TrayEvent>) {
     let (tx, rx) = mpsc::channel(1);
     let mut tray = TrayItem::new("Gon", TrayIcon::Default.icon_source()).unwrap();
 
+    if let Ok(id) = tray.inner_mut().add_menu_item_with_id("No peers", || {}) {
+        let _ = PEERS_ITEM.set(id);
+        let _ = tray.inner_mut().add_separator();
+    }
+
     add_menu_item(&mut tray, tx.clone(), TrayEvent::BecomeHost);
     add_menu_item(&mut tray, tx.clone(), TrayEvent::BecomeClient);
     add_menu_item(&mut tray, tx.clone(), TrayEvent::Quit);
@@ -69,13 +83,39 @@ pub fn set_icon(tray: &mut TrayItem, icon: TrayIcon) {
     tray.set_icon(icon.icon_source()).unwrap();
 }
 
+pub fn set_peers(tray: &mut TrayItem, peers: &[Peer]) {
+    let Some(id) = PEERS_ITEM.get() else {
+        return;
+    };
+
+    let label = if peers.is_empty() {
+        "No peers".to_string()
+    } else {
+        let names: Vec<String> = peers
+            .iter()
+            .take(MAX_TRAY_PEERS)
+            .map(|peer| if peer.is_host() { format!("{} (host)", peer.label()) } else { peer.label() })
+            .collect();
+        let more = peers.len().saturating_sub(MAX_TRAY_PEERS);
+        if more > 0 {
+            format!("Peers: {} +{}", names.join(", "), more)
+        } else {
+            format!("Peers: {}", names.join(", "))
+        }
+    };
+
+    if let Err(e) = tray.inner_mut().set_menu_item_label(&label, *id) {
+        error!("Error updating peers in tray: {:?}", e);
+    }
+}
+
 fn add_menu_item(tray: &mut TrayItem, tx: Sender<TrayEvent>,  event: TrayEvent) {
     tray.add_menu_item(event.to_string().as_str(), move || {
         let _tx = tx.clone();
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.spawn(async move {
             if let Err(e) = _tx.send(event).await {
-                println!("Error sending event: {:?}", e);
+                error!("Error sending event: {:?}", e);
             }
         });
     }).unwrap();